@@ -1,20 +1,29 @@
 pub use cop0::*;
 pub use cpu_slow::*;
+pub use device::{BusDevice, DeviceId};
+pub use interrupt::*;
 pub use machine::*;
-pub use memory::*;
-pub use memory_bus::*;
 pub use memory_util::*;
 
 mod bus;
+mod cache_ctrl;
+mod cartridge;
 mod cop0;
 mod cpu_inst;
 mod cpu_regfile;
 mod cpu_slow;
+mod device;
+mod dma;
+mod duart;
+mod exp2;
+mod gpu;
+mod icache;
+mod interrupt;
 mod ioport;
 mod machine;
 mod machine_logger;
-mod memory;
-mod memory_bus;
+mod memctrl;
 mod memory_util;
+mod scheduler;
 mod spu;
 mod timers;
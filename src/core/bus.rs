@@ -1,16 +1,71 @@
-use super::{ioport::IoPort, machine::Machine, mem_load, mem_store, MachineMutation, MemOpSize};
+use super::{
+    cache_ctrl::CacheControl, device::DeviceId, ioport::*, machine::Machine, mem_load,
+    mem_op_bytes, mem_store, memctrl::*, MachineMutation, MemOpSize,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum RegionKind {
+    // plain memory, accessed directly without going through the handlers
+    Ram,
+    Bios,
+    Scratchpad,
+    Device,
+    // attached through Machine::attach_device, dispatched by id
+    External(DeviceId),
+}
 
 struct MemoryRegion {
     base: u32,
     size: u32,
+    kind: RegionKind,
     load: fn(&Machine, &mut MachineMutation, u32, MemOpSize) -> Result<u32, String>,
     store: fn(&mut Machine, &mut MachineMutation, u32, u32, MemOpSize) -> Result<(), String>,
+    // wait states of an access
+    timing: fn(&Machine, u32, MemOpSize) -> u32,
 }
 
+const NO_WAIT: fn(&Machine, u32, MemOpSize) -> u32 = |_: &Machine, _: u32, _: MemOpSize| 0;
+const RAM_WAIT: fn(&Machine, u32, MemOpSize) -> u32 =
+    |_: &Machine, _: u32, _: MemOpSize| RAM_ACCESS_TIME;
+
+const RAM_ACCESS_TIME: u32 = 4;
+
+// 4KB pages, each holding the index + 1 of the region covering it
+const PAGE_SHIFT: u32 = 12;
+const PAGE_COUNT: usize = 1 << (32 - PAGE_SHIFT);
+const PAGE_UNMAPPED: u8 = 0;
+// the page is shared by several regions, walk the handlers
+const PAGE_SLOW: u8 = 0xFF;
+
+const BIOS_MASK: u32 = 0x7_FFFF;
+const SCRATCHPAD_MASK: u32 = 0x3FF;
+
 pub struct Bus {
     handlers: Vec<MemoryRegion>,
     handlers_isc: Vec<MemoryRegion>,
+    pages: Vec<u8>,
+    pages_isc: Vec<u8>,
+    ram_mask: u32,
     isolate_cache: bool,
+    // physical ranges of the attached devices, by id
+    external: Vec<(u32, u32)>,
+}
+
+fn build_pages(handlers: &[MemoryRegion]) -> Vec<u8> {
+    assert!(handlers.len() < PAGE_SLOW as usize);
+    let mut pages = vec![PAGE_UNMAPPED; PAGE_COUNT];
+    // earlier regions take precedence, so paint them last
+    for (idx, region) in handlers.iter().enumerate().rev() {
+        let first = region.base >> PAGE_SHIFT;
+        let last = (region.base as u64 + region.size as u64 - 1) >> PAGE_SHIFT;
+        for page in first..=last as u32 {
+            let page_base = (page as u64) << PAGE_SHIFT;
+            let covered = page_base >= region.base as u64
+                && page_base + (1 << PAGE_SHIFT) <= region.base as u64 + region.size as u64;
+            pages[page as usize] = if covered { idx as u8 + 1 } else { PAGE_SLOW };
+        }
+    }
+    pages
 }
 
 fn bios_region(base: u32) -> MemoryRegion {
@@ -18,6 +73,7 @@ fn bios_region(base: u32) -> MemoryRegion {
         // OSROM
         base,
         size: 0x80000,
+        kind: RegionKind::Bios,
         load: |m: &Machine, _: &mut MachineMutation, addr: u32, size: MemOpSize| {
             Ok(mem_load(&m.bios, addr, size))
         },
@@ -25,20 +81,41 @@ fn bios_region(base: u32) -> MemoryRegion {
             mem_store(&mut m.bios, addr, val, size);
             Ok(())
         },
+        timing: |m: &Machine, _: u32, size: MemOpSize| m.memctrl.access_time(MEMCTRL_BIOS, size),
     }
 }
 
-fn ram_region(base: u32) -> MemoryRegion {
+fn ram_region(base: u32, size: u32) -> MemoryRegion {
     MemoryRegion {
         base,
-        size: 0x200000 * 4,
+        size,
+        kind: RegionKind::Ram,
         load: |m: &Machine, _: &mut MachineMutation, addr: u32, size: MemOpSize| {
-            Ok(mem_load(&m.ram, addr & 0x001FFFFF, size))
+            Ok(mem_load(&m.ram, addr & m.memctrl.ram_mask(), size))
         },
         store: |m: &mut Machine, _: &mut MachineMutation, addr: u32, val: u32, size: MemOpSize| {
-            mem_store(&mut m.ram, addr & 0x001FFFFF, val, size);
+            mem_store(&mut m.ram, addr & m.memctrl.ram_mask(), val, size);
             Ok(())
         },
+        timing: RAM_WAIT,
+    }
+}
+
+// unconnected part of the RAM window
+fn high_z_region(base: u32, size: u32) -> MemoryRegion {
+    MemoryRegion {
+        base,
+        size,
+        kind: RegionKind::Device,
+        load: |_: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| {
+            println!("WARN: Read from HighZ RAM window at 0x{:08X}", addr);
+            Ok(0xFFFF_FFFF)
+        },
+        store: |_: &mut Machine, _: &mut MachineMutation, addr: u32, _: u32, _: MemOpSize| {
+            println!("WARN: Write to HighZ RAM window at 0x{:08X}", addr);
+            Ok(())
+        },
+        timing: RAM_WAIT,
     }
 }
 
@@ -46,6 +123,7 @@ fn dcache_region(base: u32) -> MemoryRegion {
     MemoryRegion {
         base,
         size: 0x400,
+        kind: RegionKind::Scratchpad,
         load: |m: &Machine, _: &mut MachineMutation, addr: u32, size: MemOpSize| {
             Ok(mem_load(&m.dcache, addr, size))
         },
@@ -53,109 +131,208 @@ fn dcache_region(base: u32) -> MemoryRegion {
             mem_store(&mut m.dcache, addr, val, size);
             Ok(())
         },
+        timing: NO_WAIT,
     }
 }
 
-// Expansion ROM 0
-fn exp0_region(base: u32) -> MemoryRegion {
+// Expansion region 1, placed by EXP1_BASE_ADDR
+fn exp1_region(base: u32, size: u32) -> MemoryRegion {
     MemoryRegion {
         base,
-        size: 0x800000,
-        load: |_: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| {
-            println!("WARN: Unhandled EXP0 read at 0x{:08X}", addr);
+        size,
+        kind: RegionKind::Device,
+        load: |m: &Machine, _: &mut MachineMutation, addr: u32, size: MemOpSize| {
+            if m.cart.is_inserted() {
+                return Ok(m.cart.load(addr, size));
+            }
+            println!("WARN: Unhandled EXP1 read at 0x{:08X}", addr);
             Ok(0)
         },
-        store: |_: &mut Machine, _: &mut MachineMutation, addr: u32, _: u32, _: MemOpSize| {
-            println!("WARN: Unhandled EXP0 write at 0x{:08X}", addr);
+        store: |m: &mut Machine, _: &mut MachineMutation, addr: u32, val: u32, size: MemOpSize| {
+            if m.cart.is_inserted() {
+                m.cart.store(addr, val, size);
+                return Ok(());
+            }
+            println!("WARN: Unhandled EXP1 write at 0x{:08X}", addr);
+            Ok(())
+        },
+        timing: |m: &Machine, _: u32, size: MemOpSize| m.memctrl.access_time(MEMCTRL_EXP1, size),
+    }
+}
+
+// Expansion region 2, placed by EXP2_BASE_ADDR
+fn exp2_region(base: u32, size: u32) -> MemoryRegion {
+    MemoryRegion {
+        base,
+        size,
+        kind: RegionKind::Device,
+        load: |m: &Machine, mu: &mut MachineMutation, addr: u32, size: MemOpSize| {
+            let bytes = mem_op_bytes(size);
+            mu.exp2_load = Some((addr, bytes));
+            Ok(m.exp2.load(addr, bytes))
+        },
+        store: |m: &mut Machine, _: &mut MachineMutation, addr: u32, val: u32, size: MemOpSize| {
+            m.exp2.store(addr, val, mem_op_bytes(size));
             Ok(())
         },
+        timing: |m: &Machine, _: u32, size: MemOpSize| m.memctrl.access_time(MEMCTRL_EXP2, size),
     }
 }
 
 fn io_region(base: u32) -> MemoryRegion {
     MemoryRegion {
         base,
-        size: 0x1080,
-        load: |m: &Machine, mu: &mut MachineMutation, addr: u32, _: MemOpSize| {
-            IoPort::load(m, mu, addr)
+        size: IO_REG_SIZE,
+        kind: RegionKind::Device,
+        load: |m: &Machine, mu: &mut MachineMutation, addr: u32, size: MemOpSize| {
+            IoPort::load(m, mu, addr, size)
         },
-        store: |m: &mut Machine, _: &mut MachineMutation, addr: u32, val: u32, _: MemOpSize| {
-            IoPort::store(m, addr, val)
+        store: |m: &mut Machine, _: &mut MachineMutation, addr: u32, val: u32, size: MemOpSize| {
+            IoPort::store(m, addr, val, size)
+        },
+        timing: |m: &Machine, addr: u32, size: MemOpSize| match addr {
+            addr if (IO_SPU_BASE..IO_SPU_BASE + IO_SPU_SIZE).contains(&addr) => {
+                m.memctrl.access_time(MEMCTRL_SPU, size)
+            }
+            IO_CDROM_REG0..=IO_CDROM_REG3 => m.memctrl.access_time(MEMCTRL_CDROM, size),
+            _ => 0,
         },
     }
 }
 
-fn isolate_cache_region(base: u32) -> MemoryRegion {
+fn external_region(base: u32, size: u32, id: DeviceId) -> MemoryRegion {
     MemoryRegion {
         base,
-        size: 0x40000000,
+        size,
+        kind: RegionKind::External(id),
         load: |_: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| {
-            println!("WARN: Unhandled isolate cache read at 0x{:08X}", addr);
-            Ok(0)
+            Err(format!(
+                "External device load at 0x{:08X} not routed by id",
+                addr
+            ))
         },
         store: |_: &mut Machine, _: &mut MachineMutation, addr: u32, _: u32, _: MemOpSize| {
-            println!("WARN: Unhandled isolate cache write at 0x{:08X}", addr);
+            Err(format!(
+                "External device store at 0x{:08X} not routed by id",
+                addr
+            ))
+        },
+        timing: NO_WAIT,
+    }
+}
+
+fn isolate_cache_region(base: u32) -> MemoryRegion {
+    MemoryRegion {
+        base,
+        size: 0x40000000,
+        kind: RegionKind::Device,
+        load: |m: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| {
+            Ok(m.icache.isolated_load(addr))
+        },
+        store: |m: &mut Machine, _: &mut MachineMutation, addr: u32, val: u32, _: MemOpSize| {
+            m.icache.isolated_store(addr, val);
             Ok(())
         },
+        timing: NO_WAIT,
     }
 }
 
-fn cache_ctrl(base: u32) -> MemoryRegion {
+fn cache_ctrl_region(base: u32) -> MemoryRegion {
     MemoryRegion {
         base,
         size: 0x10,
-        load: |_: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| {
-            println!("WARN: Unhandled cache control read at 0x{:08X}", addr);
-            Ok(0)
+        kind: RegionKind::Device,
+        load: |m: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| {
+            if addr == 0 {
+                Ok(m.cache_ctrl.load())
+            } else {
+                println!("WARN: Unhandled cache control read at 0x{:08X}", addr);
+                Ok(0)
+            }
         },
-        store: |_: &mut Machine, _: &mut MachineMutation, addr: u32, _: u32, _: MemOpSize| {
-            println!("WARN: Unhandled cache control write at 0x{:08X}", addr);
+        store: |m: &mut Machine, _: &mut MachineMutation, addr: u32, val: u32, _: MemOpSize| {
+            if addr == 0 {
+                m.cache_ctrl.store(val);
+                m.icache.configure(&m.cache_ctrl);
+                m.bus.configure(&m.cache_ctrl, &m.memctrl);
+            } else {
+                println!("WARN: Unhandled cache control write at 0x{:08X}", addr);
+            }
             Ok(())
         },
+        timing: NO_WAIT,
     }
 }
 
 impl Bus {
     pub fn new() -> Bus {
+        let mut bus = Bus {
+            handlers: vec![],
+            handlers_isc: vec![],
+            pages: vec![],
+            pages_isc: vec![],
+            ram_mask: 0,
+            isolate_cache: false,
+            external: vec![],
+        };
+        bus.configure(&CacheControl::new(), &MemoryControl::new());
+        bus
+    }
+
+    // takes effect on the next configure
+    pub fn set_external(&mut self, ranges: Vec<(u32, u32)>) {
+        self.external = ranges;
+    }
+
+    // rebuild the region tables after a cache or memory control write
+    pub fn configure(&mut self, cache_ctrl: &CacheControl, memctrl: &MemoryControl) {
         const KUSEG: u32 = 0x0000_0000;
         const KSEG0: u32 = 0x8000_0000;
         const KSEG1: u32 = 0xa000_0000;
 
-        Bus {
-            handlers: vec![
-                // KUSEG
-                ram_region(KUSEG + 0x0000_0000),
-                exp0_region(KUSEG + 0x1F00_0000),
-                dcache_region(KUSEG + 0x1F80_0000),
-                io_region(KUSEG + 0x1F80_1000),
-                bios_region(KUSEG + 0x1FC0_0000),
-                // KSEG0
-                ram_region(KSEG0 + 0x0000_0000),
-                exp0_region(KSEG0 + 0x1F00_0000),
-                dcache_region(KSEG0 + 0x1F80_0000),
-                io_region(KSEG0 + 0x1F80_1000),
-                bios_region(KSEG0 + 0x1FC0_0000),
-                // KSEG1
-                ram_region(KSEG1 + 0x0000_0000),
-                exp0_region(KSEG1 + 0x1F00_0000),
-                io_region(KSEG1 + 0x1F80_1000),
-                bios_region(KSEG1 + 0x1FC0_0000),
-                // OTHER
-                cache_ctrl(0xFFFE_0130),
-            ],
-            handlers_isc: vec![
-                // KUSEG
-                bios_region(KUSEG + 0x1FC0_0000),
-                // KSEG0
-                bios_region(KSEG0 + 0x1FC0_0000),
-                // KSEG1
-                bios_region(KSEG1 + 0x1FC0_0000),
-                // cache fallback
-                isolate_cache_region(KUSEG),
-                isolate_cache_region(KSEG0),
-            ],
-            isolate_cache: false,
+        let window = memctrl.ram_window();
+        let (exp1_base, exp1_size) = memctrl.exp1_region();
+        let (exp2_base, exp2_size) = memctrl.exp2_region();
+
+        let mut handlers = vec![];
+        for seg in [KUSEG, KSEG0, KSEG1] {
+            // attached devices shadow the built in regions
+            for (id, &(base, size)) in self.external.iter().enumerate() {
+                handlers.push(external_region(seg + base, size, id));
+            }
+            // the locked part of the 8MB RAM window is left unmapped and raises bus errors
+            handlers.push(ram_region(seg, window.memory));
+            if window.high_z != 0 {
+                handlers.push(high_z_region(seg + window.memory, window.high_z));
+            }
+            // the scratchpad is only mapped while enabled, and never in KSEG1
+            if seg != KSEG1 && cache_ctrl.scratchpad_enabled() {
+                handlers.push(dcache_region(seg + 0x1F80_0000));
+            }
+            handlers.push(io_region(seg + 0x1F80_1000));
+            handlers.push(bios_region(seg + 0x1FC0_0000));
+            handlers.push(exp1_region(seg + exp1_base, exp1_size));
+            handlers.push(exp2_region(seg + exp2_base, exp2_size));
         }
+        // OTHER
+        handlers.push(cache_ctrl_region(0xFFFE_0130));
+
+        self.handlers = handlers;
+        self.handlers_isc = vec![
+            // KUSEG
+            bios_region(KUSEG + 0x1FC0_0000),
+            // KSEG0
+            bios_region(KSEG0 + 0x1FC0_0000),
+            // KSEG1
+            bios_region(KSEG1 + 0x1FC0_0000),
+            // cache fallback
+            isolate_cache_region(KUSEG),
+            isolate_cache_region(KSEG0),
+            cache_ctrl_region(0xFFFE_0130),
+        ];
+        self.pages = build_pages(&self.handlers);
+        self.pages_isc = build_pages(&self.handlers_isc);
+        self.ram_mask = memctrl.ram_mask();
     }
 
     pub fn lb(m: &Machine, mu: &mut MachineMutation, addr: u32) -> Result<u32, String> {
@@ -177,7 +354,19 @@ impl Bus {
         size: MemOpSize,
     ) -> Result<u32, String> {
         match m.bus.lookup_region(addr) {
-            Some(region) => (region.load)(m, mu, addr - region.base, size),
+            Some(region) => match region.kind {
+                RegionKind::Ram => Ok(mem_load(&m.ram, addr & m.bus.ram_mask, size)),
+                RegionKind::Bios => Ok(mem_load(&m.bios, addr & BIOS_MASK, size)),
+                RegionKind::Scratchpad => Ok(mem_load(&m.dcache, addr & SCRATCHPAD_MASK, size)),
+                RegionKind::Device => (region.load)(m, mu, addr - region.base, size),
+                RegionKind::External(id) => {
+                    let offset = addr - region.base;
+                    let device = m.devices.get(id).ok_or(format!("No device {}", id))?;
+                    let val = device.load(offset, size)?;
+                    mu.device_load = Some((id, offset, size));
+                    Ok(val)
+                }
+            },
             None => Err(format!("Unhandled memory read at 0x{:08X}", addr)),
         }
     }
@@ -192,37 +381,116 @@ impl Bus {
         match m.bus.lookup_region(addr) {
             Some(region) => {
                 println!("MEM_WRITE 0x{:08X} <= {:08X}", addr, val);
-                (region.store)(m, mu, addr - region.base, val, size)
+                match region.kind {
+                    RegionKind::Ram => {
+                        let ram_mask = m.bus.ram_mask;
+                        mem_store(&mut m.ram, addr & ram_mask, val, size);
+                        Ok(())
+                    }
+                    RegionKind::Bios => {
+                        mem_store(&mut m.bios, addr & BIOS_MASK, val, size);
+                        Ok(())
+                    }
+                    RegionKind::Scratchpad => {
+                        mem_store(&mut m.dcache, addr & SCRATCHPAD_MASK, val, size);
+                        Ok(())
+                    }
+                    RegionKind::Device => (region.store)(m, mu, addr - region.base, val, size),
+                    RegionKind::External(id) => {
+                        let offset = addr - region.base;
+                        match m.devices.get_mut(id) {
+                            Some(device) => device.store(offset, val, size),
+                            None => Err(format!("No device {}", id)),
+                        }
+                    }
+                }
             }
             None => Err(format!("Unhandled memory write at 0x{:08X}", addr)),
         }
     }
 
+    // wait states of a load or store at addr
+    pub fn access_time(m: &Machine, addr: u32, size: MemOpSize) -> u32 {
+        match m.bus.lookup_region(addr) {
+            Some(region) => match region.kind {
+                RegionKind::External(id) => match m.devices.get(id) {
+                    Some(device) => device.access_time(addr - region.base, size),
+                    None => 0,
+                },
+                _ => (region.timing)(m, addr - region.base, size),
+            },
+            None => 0,
+        }
+    }
+
+    pub fn is_mapped(&self, addr: u32) -> bool {
+        self.lookup_region(addr).is_some()
+    }
+
     fn lookup_region(&self, addr: u32) -> Option<&MemoryRegion> {
-        let handlers = if self.isolate_cache {
-            // &self.handlers_isc
-            &self.handlers_isc
+        let (handlers, pages) = if self.isolate_cache {
+            (&self.handlers_isc, &self.pages_isc)
         } else {
-            &self.handlers
+            (&self.handlers, &self.pages)
         };
 
-        for region in handlers {
-            if addr >= region.base {
-                // println!("lookup_region: 0x{:08X} >= 0x{:08X}", addr, region.base);
-                if addr < region.base + region.size {
-                    return Some(region);
-                }
-            }
+        match pages[(addr >> PAGE_SHIFT) as usize] {
+            PAGE_UNMAPPED => None,
+            PAGE_SLOW => Bus::walk_regions(handlers, addr),
+            idx => Some(&handlers[idx as usize - 1]),
         }
+    }
 
-        None
+    fn walk_regions(handlers: &[MemoryRegion], addr: u32) -> Option<&MemoryRegion> {
+        handlers
+            .iter()
+            .find(|region| addr >= region.base && addr - region.base < region.size)
     }
 
     pub fn mutate(m: &mut Machine, mu: &mut MachineMutation) -> Result<(), String> {
-        if let Some((addr, val, size)) = mu.bus_write {
+        for (addr, val, size) in [mu.bus_write, mu.bus_write_split].into_iter().flatten() {
             Bus::store(m, mu, addr, val, size)?;
+            if m.bus.isolate_cache {
+                m.cpu.flush_decoded();
+            } else {
+                m.cpu.invalidate_decoded(addr);
+            }
         }
         m.bus.isolate_cache = m.cop0.status_isc();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages_match_walk() {
+        let mut cache_ctrl = CacheControl::new();
+        cache_ctrl.store(0x0001_E988);
+        let mut bus = Bus::new();
+        bus.configure(&cache_ctrl, &MemoryControl::new());
+        for (handlers, pages) in [
+            (&bus.handlers, &bus.pages),
+            (&bus.handlers_isc, &bus.pages_isc),
+        ] {
+            for addr in (0..=u32::MAX).step_by(0x0FF1).chain([
+                0x1F80_03FF,
+                0x1F80_0400,
+                0x1F80_2080,
+                0xFFFE_0130,
+                0xFFFE_0140,
+            ]) {
+                let expected = Bus::walk_regions(handlers, addr).map(|r| r.base);
+                let found = match pages[(addr >> PAGE_SHIFT) as usize] {
+                    PAGE_UNMAPPED => None,
+                    PAGE_SLOW => Bus::walk_regions(handlers, addr),
+                    idx => Some(&handlers[idx as usize - 1]),
+                }
+                .map(|r| r.base);
+                assert_eq!(found, expected, "0x{:08X}", addr);
+            }
+        }
+    }
+}
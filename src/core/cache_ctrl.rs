@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+// BIU/cache configuration register at 0xFFFE0130
+pub const CACHE_CTRL_LOCK: u32 = 0x0000_0001;
+pub const CACHE_CTRL_INV: u32 = 0x0000_0002;
+pub const CACHE_CTRL_TAG: u32 = 0x0000_0004;
+pub const CACHE_CTRL_RAM: u32 = 0x0000_0008;
+pub const CACHE_CTRL_DS: u32 = 0x0000_0080;
+pub const CACHE_CTRL_IS1: u32 = 0x0000_0800;
+
+// the caches are write-through without write allocate: stores always go to memory and
+// never fill or update an I-cache line, only isolated stores reach the cache
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CacheControl {
+    reg: u32,
+}
+
+impl CacheControl {
+    pub fn new() -> CacheControl {
+        CacheControl { reg: 0 }
+    }
+
+    pub fn load(&self) -> u32 {
+        self.reg
+    }
+
+    pub fn store(&mut self, val: u32) {
+        println!("CACHE_CTRL: <= {:08X}", val);
+        self.reg = val;
+    }
+
+    // the D-cache is used as scratchpad RAM only when both enable bits are set
+    pub fn scratchpad_enabled(&self) -> bool {
+        self.reg & (CACHE_CTRL_RAM | CACHE_CTRL_DS) == (CACHE_CTRL_RAM | CACHE_CTRL_DS)
+    }
+
+    pub fn icache_enabled(&self) -> bool {
+        self.reg & CACHE_CTRL_IS1 != 0
+    }
+
+    // isolated stores rewrite the tag and invalidate the line
+    pub fn tag_test(&self) -> bool {
+        self.reg & CACHE_CTRL_TAG != 0
+    }
+
+    // isolated stores invalidate the line
+    pub fn invalidate_mode(&self) -> bool {
+        self.reg & CACHE_CTRL_INV != 0
+    }
+
+    // isolated stores load the word into the cache as valid
+    pub fn lock_mode(&self) -> bool {
+        self.reg & CACHE_CTRL_LOCK != 0
+    }
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use super::{mem_op_bytes, MemOpSize};
+
+// parallel port cartridges (Action Replay, Xplorer, Caetla) carry a JEDEC flash in EXP1
+pub const CART_MAX_SIZE: usize = 0x80_0000;
+pub const CART_LICENSE_OFFSET: usize = 0x84;
+pub const CART_LICENSE: &[u8] = b"Licensed by Sony Computer Entertainment Inc.";
+
+const FLASH_CMD_ADDR1: u32 = 0x5555;
+const FLASH_CMD_ADDR2: u32 = 0x2AAA;
+const FLASH_CMD_ADDR_MASK: u32 = 0x7FFF;
+const FLASH_SECTOR_SIZE: usize = 0x4000;
+
+const FLASH_CMD_ID: u8 = 0x90;
+const FLASH_CMD_RESET: u8 = 0xF0;
+const FLASH_CMD_PROGRAM: u8 = 0xA0;
+const FLASH_CMD_ERASE: u8 = 0x80;
+const FLASH_CMD_ERASE_CHIP: u8 = 0x10;
+const FLASH_CMD_ERASE_SECTOR: u8 = 0x30;
+
+// AMD Am29F010
+const FLASH_MANUFACTURER_ID: u8 = 0x01;
+const FLASH_DEVICE_ID: u8 = 0x20;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+enum FlashState {
+    Read,
+    // unlock cycles of a command, counting the ones seen
+    Unlock1,
+    Unlock2,
+    Id,
+    Program,
+    EraseUnlock0,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Cartridge {
+    rom: Vec<u8>,
+    state: FlashState,
+    // the unlock sequence ended by an erase setup command
+    erase_setup: bool,
+}
+
+impl Cartridge {
+    pub fn new() -> Cartridge {
+        Cartridge {
+            rom: vec![],
+            state: FlashState::Read,
+            erase_setup: false,
+        }
+    }
+
+    pub fn insert(&mut self, rom: Vec<u8>) -> Result<(), String> {
+        if rom.is_empty() || rom.len() > CART_MAX_SIZE {
+            return Err(format!("Invalid cartridge size 0x{:X}", rom.len()));
+        }
+        if rom.get(CART_LICENSE_OFFSET..CART_LICENSE_OFFSET + CART_LICENSE.len())
+            != Some(CART_LICENSE)
+        {
+            println!("WARN: Cartridge has no license string, the BIOS will not boot it");
+        }
+        // unused flash reads as erased, and the chip is mirrored through the region
+        let size = rom.len().next_power_of_two();
+        self.rom = rom;
+        self.rom.resize(size, 0xFF);
+        self.state = FlashState::Read;
+        self.erase_setup = false;
+        Ok(())
+    }
+
+    pub fn is_inserted(&self) -> bool {
+        !self.rom.is_empty()
+    }
+
+    // the current flash contents, for writing back to the image
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn read_byte(&self, offset: u32) -> u8 {
+        match self.state {
+            FlashState::Id => match offset & 0xFF {
+                0 => FLASH_MANUFACTURER_ID,
+                1 => FLASH_DEVICE_ID,
+                _ => 0,
+            },
+            _ => self.rom[offset as usize & (self.rom.len() - 1)],
+        }
+    }
+
+    // the 8-bit bus splits wider accesses into consecutive bytes
+    pub fn load(&self, offset: u32, size: MemOpSize) -> u32 {
+        let bytes = mem_op_bytes(size);
+        (0..bytes).fold(0, |val, i| {
+            val | (self.read_byte(offset + i) as u32) << (8 * i)
+        })
+    }
+
+    pub fn store(&mut self, offset: u32, val: u32, size: MemOpSize) {
+        let bytes = mem_op_bytes(size);
+        for i in 0..bytes {
+            self.write_byte(offset + i, (val >> (8 * i)) as u8);
+        }
+    }
+
+    fn write_byte(&mut self, offset: u32, val: u8) {
+        let cmd_addr = offset & FLASH_CMD_ADDR_MASK;
+        self.state = match (self.state, cmd_addr, val) {
+            (FlashState::Program, _, _) => {
+                // programming can only clear bits
+                let idx = offset as usize & (self.rom.len() - 1);
+                self.rom[idx] &= val;
+                FlashState::Read
+            }
+            // a reset is accepted in any other state
+            (_, _, FLASH_CMD_RESET) => {
+                self.erase_setup = false;
+                FlashState::Read
+            }
+            (FlashState::Read | FlashState::Id, FLASH_CMD_ADDR1, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, FLASH_CMD_ADDR2, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, _, FLASH_CMD_ERASE_SECTOR) if self.erase_setup => {
+                self.erase_setup = false;
+                let start = offset as usize & (self.rom.len() - 1) & !(FLASH_SECTOR_SIZE - 1);
+                let end = (start + FLASH_SECTOR_SIZE).min(self.rom.len());
+                self.rom[start..end].fill(0xFF);
+                FlashState::Read
+            }
+            (FlashState::Unlock2, FLASH_CMD_ADDR1, cmd) if self.erase_setup => {
+                self.erase_setup = false;
+                if cmd == FLASH_CMD_ERASE_CHIP {
+                    self.rom.fill(0xFF);
+                } else {
+                    println!("WARN: Unknown cartridge erase command {:02X}", cmd);
+                }
+                FlashState::Read
+            }
+            (FlashState::Unlock2, FLASH_CMD_ADDR1, FLASH_CMD_ID) => FlashState::Id,
+            (FlashState::Unlock2, FLASH_CMD_ADDR1, FLASH_CMD_PROGRAM) => FlashState::Program,
+            (FlashState::Unlock2, FLASH_CMD_ADDR1, FLASH_CMD_ERASE) => FlashState::EraseUnlock0,
+            (FlashState::EraseUnlock0, FLASH_CMD_ADDR1, 0xAA) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, FLASH_CMD_ADDR2, 0x55) => FlashState::EraseUnlock2,
+            _ => {
+                println!(
+                    "WARN: Unexpected cartridge write {:02X} at 0x{:08X} in {:?}",
+                    val, offset, self.state
+                );
+                self.erase_setup = false;
+                FlashState::Read
+            }
+        };
+        // the second unlock of an erase continues like a normal command
+        if self.state == FlashState::EraseUnlock2 {
+            self.erase_setup = true;
+            self.state = FlashState::Unlock2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{bus::Bus, Machine, MachineMutation};
+
+    fn cart() -> Cartridge {
+        let mut rom = vec![0; 0x20000];
+        rom[CART_LICENSE_OFFSET..CART_LICENSE_OFFSET + CART_LICENSE.len()]
+            .copy_from_slice(CART_LICENSE);
+        rom[0x4000] = 0x12;
+        let mut cart = Cartridge::new();
+        cart.insert(rom).unwrap();
+        cart
+    }
+
+    fn command(cart: &mut Cartridge, cmd: u8) {
+        cart.store(0x5555, 0xAA, MemOpSize::Byte);
+        cart.store(0x2AAA, 0x55, MemOpSize::Byte);
+        cart.store(0x5555, cmd as u32, MemOpSize::Byte);
+    }
+
+    #[test]
+    fn test_flash_commands() {
+        let mut cart = cart();
+        assert_eq!(cart.load(0x84, MemOpSize::Word), 0x6563694C);
+
+        command(&mut cart, FLASH_CMD_ID);
+        assert_eq!(cart.load(0, MemOpSize::Half), 0x2001);
+        cart.store(0, FLASH_CMD_RESET as u32, MemOpSize::Byte);
+        assert_eq!(cart.load(0, MemOpSize::Byte), 0x00);
+
+        // erase the second sector, then program a byte in it
+        command(&mut cart, FLASH_CMD_ERASE);
+        cart.store(0x5555, 0xAA, MemOpSize::Byte);
+        cart.store(0x2AAA, 0x55, MemOpSize::Byte);
+        cart.store(0x4100, FLASH_CMD_ERASE_SECTOR as u32, MemOpSize::Byte);
+        assert_eq!(cart.load(0x4000, MemOpSize::Byte), 0xFF);
+        assert_eq!(cart.load(0x3FFF, MemOpSize::Byte), 0x00);
+        command(&mut cart, FLASH_CMD_PROGRAM);
+        cart.store(0x4001, 0x5A, MemOpSize::Byte);
+        assert_eq!(cart.load(0x4000, MemOpSize::Half), 0x5AFF);
+
+        // plain writes do not modify the flash
+        cart.store(0x4002, 0x00, MemOpSize::Byte);
+        assert_eq!(cart.load(0x4002, MemOpSize::Byte), 0xFF);
+
+        command(&mut cart, FLASH_CMD_ERASE);
+        command(&mut cart, FLASH_CMD_ERASE_CHIP);
+        assert_eq!(cart.load(0x84, MemOpSize::Word), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn test_cartridge_mapped_in_exp1() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        m.insert_cartridge(cart().rom().to_vec()).unwrap();
+        let mut mu = MachineMutation::new();
+        assert_eq!(Bus::lw(&m, &mut mu, 0x1F00_0084).unwrap(), 0x6563694C);
+        assert_eq!(Bus::lb(&m, &mut mu, 0xBF00_4000).unwrap(), 0x12);
+    }
+}
@@ -9,8 +9,7 @@ pub struct Cop0 {
     reg_cause: u32,
     reg_cache: u32,
     reg_epc: u32,
-    int_mask: u16,
-    int_reqs: u16,
+    reg_badvaddr: u32,
 }
 
 #[derive(Copy, Clone)]
@@ -18,6 +17,10 @@ pub struct Cop0ExceptionParams {
     // 0: Address, 1: BD
     pub epc: (u32, bool),
     pub cause: u8,
+    // faulting address for ADEL/ADES
+    pub badvaddr: Option<u32>,
+    // CE field for coprocessor unusable exceptions
+    pub coprocessor: u8,
 }
 
 const STATUS_PUSH_MASK: u32 = 0x0000_000F;
@@ -26,6 +29,11 @@ const CACHE_PUSH_MASK: u32 = 0x0000_0F00;
 const CACHE_POP_MASK: u32 = 0x0000_3C00;
 const CAUSE_BD_MASK: u32 = 0x8000_0000;
 const CAUSE_EXC_MASK: u32 = 0x0000_007C;
+const CAUSE_CE_MASK: u32 = 0x3000_0000;
+const CAUSE_SW_INT_MASK: u32 = 0x0000_0300;
+const CAUSE_HW_INT: u32 = 0x0000_0400;
+const STATUS_IEC: u32 = 0x0000_0001;
+const STATUS_IM_MASK: u32 = 0x0000_FF00;
 
 impl Cop0 {
     pub fn new() -> Cop0 {
@@ -34,8 +42,7 @@ impl Cop0 {
             reg_cause: 0,
             reg_cache: 0,
             reg_epc: 0,
-            int_mask: 0,
-            int_reqs: 0,
+            reg_badvaddr: 0,
         }
     }
 
@@ -43,6 +50,15 @@ impl Cop0 {
         return self.reg_status & 0x10000 != 0;
     }
 
+    pub fn status_cu(&self, copn: u8) -> bool {
+        self.reg_status & (0x1000_0000 << copn) != 0
+    }
+
+    // current mode is user mode
+    pub fn status_kuc(&self) -> bool {
+        self.reg_status & 0x0002 != 0
+    }
+
     pub fn load(&self, idx: u8) -> Result<u32, String> {
         match idx {
             COP0_CONFIG => {
@@ -61,6 +77,10 @@ impl Cop0 {
                 println!("COP0: CACHE => {:08X}", self.reg_cache);
                 Ok(self.reg_cache)
             }
+            COP0_BADVADDR => {
+                println!("COP0: BADVADDR => {:08X}", self.reg_badvaddr);
+                Ok(self.reg_badvaddr)
+            }
             COP0_UNK9 => {
                 println!("COP0: UNK9 => {:08X}", 0);
                 Ok(0)
@@ -85,7 +105,19 @@ impl Cop0 {
         }
     }
 
-    pub fn cycle(&self) {}
+    // hw_interrupt: I_STAT & I_MASK, wired to IP2
+    pub fn cycle(&mut self, hw_interrupt: bool) {
+        if hw_interrupt {
+            self.reg_cause |= CAUSE_HW_INT;
+        } else {
+            self.reg_cause &= !CAUSE_HW_INT;
+        }
+    }
+
+    // an enabled interrupt is waiting to be taken before the next instruction
+    pub fn interrupt_pending(&self) -> bool {
+        self.reg_status & STATUS_IEC != 0 && self.reg_cause & self.reg_status & STATUS_IM_MASK != 0
+    }
 
     pub fn mutate(&mut self, mu: &mut MachineMutation) -> Result<(), String> {
         match mu.cop0_write {
@@ -103,6 +135,10 @@ impl Cop0 {
                     println!("COP0: CACHE <= {:08X}", val);
                     self.reg_cache = val;
                 }
+                COP0_BADVADDR => {
+                    // read-only
+                    println!("COP0: BADVADDR <= {:08X} (ignored)", val);
+                }
                 COP0_UNK9 => {
                     println!("COP0: UNK9 <= {:08X}", val);
                 }
@@ -115,7 +151,9 @@ impl Cop0 {
                 }
                 COP0_CAUSE => {
                     println!("COP0: CAUSE <= {:08X}", val);
-                    self.reg_cause = val;
+                    // only the software interrupt bits are writable
+                    self.reg_cause =
+                        (self.reg_cause & !CAUSE_SW_INT_MASK) | (val & CAUSE_SW_INT_MASK);
                 }
                 COP0_EPC => {
                     println!("COP0: EPC <= {:08X}", val);
@@ -136,28 +174,16 @@ impl Cop0 {
         Ok(())
     }
 
-    pub fn int_stat(&self) -> u32 {
-        self.int_reqs as u32
-    }
-
-    pub fn int_mask(&self) -> u32 {
-        self.int_mask as u32
-    }
-
-    pub fn int_set_mask(&mut self, mask: u32) {
-        self.int_mask = mask as u16;
-    }
-
-    pub fn int_set_stat(&mut self, stat: u32) {
-        self.int_reqs = stat as u16;
-    }
-
     fn raise_exception(&mut self, e: &Cop0ExceptionParams, mu: &mut MachineMutation) {
         self.push_mode();
         self.reg_cause =
             (self.reg_cause & !CAUSE_BD_MASK) | if e.epc.1 { CAUSE_BD_MASK } else { 0 };
         self.reg_cause = (self.reg_cause & !CAUSE_EXC_MASK) | ((e.cause as u32) << 2);
+        self.reg_cause = (self.reg_cause & !CAUSE_CE_MASK) | ((e.coprocessor as u32 & 0x3) << 28);
         self.reg_epc = e.epc.0;
+        if let Some(addr) = e.badvaddr {
+            self.reg_badvaddr = addr;
+        }
         mu.exception_branch = match e.cause {
             EXCEPTION_TLBL | EXCEPTION_TLBS => Some(0x80000000),
             _ => Some(0x80000080),
@@ -117,6 +117,7 @@ pub const COP0_CONFIG: u8 = 0x03;
 pub const COP0_UNK5: u8 = 0x05;
 pub const COP0_UNK6: u8 = 0x06;
 pub const COP0_CACHE: u8 = 0x07;
+pub const COP0_BADVADDR: u8 = 0x08;
 pub const COP0_UNK9: u8 = 0x09;
 pub const COP0_UNK11: u8 = 0x0B;
 pub const COP0_STATUS: u8 = 0x0C;
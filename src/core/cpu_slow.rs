@@ -47,7 +47,42 @@ impl CpuFetch {
     }
 }
 
+// decoded instructions are keyed by physical address so that writes through any mirror evict them
+fn decode_cache_key(addr: u32) -> u32 {
+    let phys = addr & 0x1FFF_FFFC;
+    if phys < 0x0080_0000 {
+        // RAM is mirrored every 2MB
+        phys & 0x001F_FFFF
+    } else {
+        phys
+    }
+}
+
+// HI/LO latency of the multiplier, by the magnitude of rs
+const MULT_CYCLES_SMALL: u32 = 6;
+const MULT_CYCLES_MEDIUM: u32 = 9;
+const MULT_CYCLES_LARGE: u32 = 13;
+const DIV_CYCLES: u32 = 36;
+
+const WRITE_BUFFER_DEPTH: usize = 4;
+
+// classed by the significant bits of rs, leading sign bits of negative values don't count
+fn mult_cycles(bits: u32) -> u32 {
+    if bits < 0x800 {
+        MULT_CYCLES_SMALL
+    } else if bits < 0x10_0000 {
+        MULT_CYCLES_MEDIUM
+    } else {
+        MULT_CYCLES_LARGE
+    }
+}
+
+fn is_scratchpad(addr: u32) -> bool {
+    addr < 0xA000_0000 && (addr & 0x1FFF_FFFF) >> 10 == 0x1F80_0000 >> 10
+}
+
 pub struct CpuInstEntry {
+    inst: u32,
     decoded: CpuInst,
     inststr: String,
 }
@@ -58,8 +93,16 @@ pub struct CpuSlow {
 
     fetch: CpuFetch,
 
+    // load issued by the previous instruction, written back after the current one
+    load_delay: Option<(u8, u32)>,
+
+    // clock at which the multiplier/divider result becomes available
+    hilo_ready: u64,
+    // completion clocks of the last stores queued in the write buffer, oldest first
+    write_buffer: [u64; WRITE_BUFFER_DEPTH],
+
     #[serde(skip)]
-    icache: HashMap<u32, Rc<CpuInstEntry>>,
+    decode_cache: HashMap<u32, Rc<CpuInstEntry>>,
 }
 
 impl CpuSlow {
@@ -67,7 +110,10 @@ impl CpuSlow {
         CpuSlow {
             reg: CpuRegfile::new(),
             fetch: CpuFetch::new(),
-            icache: HashMap::new(),
+            load_delay: None,
+            hilo_ready: 0,
+            write_buffer: [0; WRITE_BUFFER_DEPTH],
+            decode_cache: HashMap::new(),
         }
     }
 
@@ -75,12 +121,16 @@ impl CpuSlow {
         CpuSlow {
             reg: self.reg.clone(),
             fetch: self.fetch.clone(),
-            icache: HashMap::new(),
+            load_delay: self.load_delay,
+            hilo_ready: self.hilo_ready,
+            write_buffer: self.write_buffer,
+            decode_cache: HashMap::new(),
         }
     }
 
     pub fn reset(&mut self) {
-        self.fetch.reset()
+        self.fetch.reset();
+        self.load_delay = None;
     }
 
     pub fn current_pc(&self) -> u32 {
@@ -95,30 +145,184 @@ impl CpuSlow {
         }
     }
 
+    fn exception(&self, mu: &mut MachineMutation, cause: u8) {
+        mu.cop0_exception = Some(Cop0ExceptionParams {
+            cause,
+            epc: self.epc(),
+            badvaddr: None,
+            coprocessor: 0,
+        });
+    }
+
+    fn coprocessor_exception(&self, mu: &mut MachineMutation, copn: u8) {
+        mu.cop0_exception = Some(Cop0ExceptionParams {
+            cause: EXCEPTION_CPU,
+            epc: self.epc(),
+            badvaddr: None,
+            coprocessor: copn,
+        });
+    }
+
+    fn coprocessor_usable(&self, m: &Machine, copn: u8) -> bool {
+        // COP0 is always usable in kernel mode
+        (copn == 0 && !m.cop0.status_kuc()) || m.cop0.status_cu(copn)
+    }
+
+    fn address_exception(&self, mu: &mut MachineMutation, cause: u8, addr: u32) {
+        mu.cop0_exception = Some(Cop0ExceptionParams {
+            cause,
+            epc: self.epc(),
+            badvaddr: Some(addr),
+            coprocessor: 0,
+        });
+    }
+
+    // raises ADEL/ADES or DBE and returns false when the data access can't be performed
+    fn check_access(
+        &self,
+        m: &Machine,
+        mu: &mut MachineMutation,
+        addr: u32,
+        size: MemOpSize,
+        store: bool,
+    ) -> bool {
+        let align_mask = match size {
+            MemOpSize::Byte => 0,
+            MemOpSize::Half => 1,
+            MemOpSize::Word => 3,
+        };
+        if addr & align_mask != 0 || (m.cop0.status_kuc() && addr >= 0x8000_0000) {
+            let cause = if store {
+                EXCEPTION_ADES
+            } else {
+                EXCEPTION_ADEL
+            };
+            self.address_exception(mu, cause, addr);
+            return false;
+        }
+        if !m.bus.is_mapped(addr) {
+            self.exception(mu, EXCEPTION_DBE);
+            return false;
+        }
+        self.access_cycles(m, mu, addr, size, store);
+        true
+    }
+
+    // stores retire through the write buffer, loads wait for it to drain
+    fn access_cycles(
+        &self,
+        m: &Machine,
+        mu: &mut MachineMutation,
+        addr: u32,
+        size: MemOpSize,
+        store: bool,
+    ) {
+        if is_scratchpad(addr) {
+            return;
+        }
+        let now = m.clock + mu.cycles as u64;
+        let last = self.write_buffer[WRITE_BUFFER_DEPTH - 1];
+        if store {
+            let oldest = self.write_buffer[0];
+            let issue = if oldest > now {
+                mu.cycles += (oldest - now) as u32;
+                oldest
+            } else {
+                now
+            };
+            let finish = issue.max(last) + Bus::access_time(m, addr, size) as u64 + 1;
+            mu.write_buffer_push = Some(finish);
+        } else {
+            if last > now {
+                mu.cycles += (last - now) as u32;
+            }
+            mu.cycles += Bus::access_time(m, addr, size);
+        }
+    }
+
+    // MFHI/MFLO stall until the multiplier/divider is done
+    fn hilo_interlock(&self, m: &Machine, mu: &mut MachineMutation) {
+        let now = m.clock + mu.cycles as u64;
+        if self.hilo_ready > now {
+            mu.cycles += (self.hilo_ready - now) as u32;
+        }
+    }
+
+    fn hilo_busy(&self, m: &Machine, mu: &mut MachineMutation, latency: u32) {
+        self.hilo_interlock(m, mu);
+        mu.hilo_ready = Some(m.clock + mu.cycles as u64 + latency as u64);
+    }
+
+    // a store hit code memory
+    pub fn invalidate_decoded(&mut self, addr: u32) {
+        self.decode_cache.remove(&decode_cache_key(addr));
+    }
+
+    // the BIOS flushes the instruction cache with isolated stores
+    pub fn flush_decoded(&mut self) {
+        self.decode_cache.clear();
+    }
+
+    // LWL/LWR merge with a load still in flight to the same register
+    fn load_gpr_bypass(&self, idx: u8) -> u32 {
+        match self.load_delay {
+            Some((reg, val)) if reg == idx => val,
+            _ => self.reg.load_gpr(idx),
+        }
+    }
+
     pub fn cycle(&self, m: &Machine, mu: &mut MachineMutation) -> Result<(), String> {
+        let pc = self.fetch.pc;
+        if m.cop0.interrupt_pending() {
+            self.exception(mu, EXCEPTION_INT);
+            return Ok(());
+        }
+        if pc & 3 != 0 || (m.cop0.status_kuc() && pc >= 0x8000_0000) {
+            self.address_exception(mu, EXCEPTION_ADEL, pc);
+            return Ok(());
+        }
+
+        if !m.bus.is_mapped(pc) {
+            self.exception(mu, EXCEPTION_IBE);
+            return Ok(());
+        }
+        let inst = if m.icache.is_cached(pc) {
+            match m.icache.fetch(pc) {
+                Some(inst) => inst,
+                None => {
+                    let base = pc & !0xF;
+                    let mut line = [0; 4];
+                    for i in ((pc & 0xF) / 4)..4 {
+                        line[i as usize] = Bus::lw(m, mu, base + i * 4)?;
+                    }
+                    // the refill is a burst: one access, then a word per cycle
+                    mu.cycles += Bus::access_time(m, pc, MemOpSize::Word) + (3 - (pc & 0xF) / 4);
+                    mu.icache_fill = Some((pc, line));
+                    line[((pc & 0xF) / 4) as usize]
+                }
+            }
+        } else {
+            mu.cycles += Bus::access_time(m, pc, MemOpSize::Word);
+            Bus::lw(m, mu, pc)?
+        };
+
         let cpu_entry: Rc<CpuInstEntry>;
-        let (decoded, inststr) = match self.icache.get(&self.fetch.pc) {
-            Some(entry) => (&entry.decoded, &entry.inststr),
-            None => {
-                let inst = Bus::lw(m, mu, self.fetch.pc)?;
+        let (decoded, inststr) = match self.decode_cache.get(&decode_cache_key(pc)) {
+            Some(entry) if entry.inst == inst => (&entry.decoded, &entry.inststr),
+            _ => {
                 let decoded_val = CpuInst::new(inst);
-                let inststr_val = format!(
-                    "{:08X}: {:08X} {}",
-                    self.fetch.pc,
-                    inst,
-                    decoded_val.to_string(),
-                );
+                let inststr_val = format!("{:08X} {}", inst, decoded_val.to_string());
                 cpu_entry = Rc::new(CpuInstEntry {
+                    inst,
                     decoded: decoded_val,
                     inststr: inststr_val,
                 });
-                mu.icache_write = Some((m.cpu.fetch.pc, cpu_entry.clone()));
+                mu.decode_cache_write = Some((decode_cache_key(pc), cpu_entry.clone()));
                 (&cpu_entry.decoded, &cpu_entry.inststr)
             }
         };
 
-        let pc = self.fetch.pc;
-        println!("{}", inststr);
+        println!("{:08X}: {}", pc, inststr);
 
         match decoded.opcode {
             OP_SPECIAL => match decoded.funct {
@@ -159,12 +363,13 @@ impl CpuSlow {
                     self.fetch.branch_to(mu, rs);
                 }
                 FUNCT_SYSCALL => {
-                    mu.cop0_exception = Some(Cop0ExceptionParams {
-                        cause: EXCEPTION_SYS,
-                        epc: self.epc(),
-                    });
+                    self.exception(mu, EXCEPTION_SYS);
+                }
+                FUNCT_BREAK => {
+                    self.exception(mu, EXCEPTION_BP);
                 }
                 FUNCT_MFHI => {
+                    self.hilo_interlock(m, mu);
                     mu.reg_write = Some((decoded.rd, self.reg.load_hi()));
                 }
                 FUNCT_MTHI => {
@@ -172,36 +377,57 @@ impl CpuSlow {
                     mu.hilo_write = Some((rs, self.reg.load_lo()));
                 }
                 FUNCT_MFLO => {
+                    self.hilo_interlock(m, mu);
                     mu.reg_write = Some((decoded.rd, self.reg.load_lo()));
                 }
                 FUNCT_MTLO => {
                     let rs = self.reg.load_gpr(decoded.rs);
                     mu.hilo_write = Some((self.reg.load_hi(), rs));
                 }
+                FUNCT_MULT => {
+                    let rs = self.reg.load_gpr(decoded.rs) as i32 as i64;
+                    let rt = self.reg.load_gpr(decoded.rt) as i32 as i64;
+                    let result = (rs * rt) as u64;
+                    self.hilo_busy(m, mu, mult_cycles((rs ^ (rs >> 63)) as u32));
+                    mu.hilo_write = Some(((result >> 32) as u32, result as u32));
+                }
+                FUNCT_MULTU => {
+                    let rs = self.reg.load_gpr(decoded.rs) as u64;
+                    let rt = self.reg.load_gpr(decoded.rt) as u64;
+                    let result = rs * rt;
+                    self.hilo_busy(m, mu, mult_cycles(rs as u32));
+                    mu.hilo_write = Some(((result >> 32) as u32, result as u32));
+                }
                 FUNCT_DIV => {
                     let rs = self.reg.load_gpr(decoded.rs) as i32;
                     let rt = self.reg.load_gpr(decoded.rt) as i32;
-                    if rt == 0 {
-                        println!("WARN: DIV by zero");
-                        mu.hilo_write = Some((0, 0)); // Undefined behavior
+                    self.hilo_busy(m, mu, DIV_CYCLES);
+                    mu.hilo_write = Some(if rt == 0 {
+                        // the divider gives up immediately: LO = -1 or +1 depending on the sign
+                        (rs as u32, if rs >= 0 { 0xFFFF_FFFF } else { 1 })
+                    } else if rs == i32::MIN && rt == -1 {
+                        (0, i32::MIN as u32)
                     } else {
-                        mu.hilo_write = Some(((rs % rt) as u32, (rs / rt) as u32));
-                    }
+                        ((rs % rt) as u32, (rs / rt) as u32)
+                    });
                 }
                 FUNCT_DIVU => {
                     let rs = self.reg.load_gpr(decoded.rs);
                     let rt = self.reg.load_gpr(decoded.rt);
-                    if rt == 0 {
-                        println!("WARN: DIV by zero");
-                        mu.hilo_write = Some((0, 0)); // Undefined behavior
+                    self.hilo_busy(m, mu, DIV_CYCLES);
+                    mu.hilo_write = Some(if rt == 0 {
+                        (rs, 0xFFFF_FFFF)
                     } else {
-                        mu.hilo_write = Some((rs % rt, rs / rt));
-                    }
+                        (rs % rt, rs / rt)
+                    });
                 }
                 FUNCT_ADD => {
                     let rs = self.reg.load_gpr(decoded.rs);
                     let rt = self.reg.load_gpr(decoded.rt);
-                    mu.reg_write = Some((decoded.rd, (rs as i32 + rt as i32) as u32));
+                    match (rs as i32).checked_add(rt as i32) {
+                        Some(val) => mu.reg_write = Some((decoded.rd, val as u32)),
+                        None => self.exception(mu, EXCEPTION_OV),
+                    }
                 }
                 FUNCT_ADDU => {
                     let rs = self.reg.load_gpr(decoded.rs);
@@ -211,7 +437,10 @@ impl CpuSlow {
                 FUNCT_SUB => {
                     let rs = self.reg.load_gpr(decoded.rs);
                     let rt = self.reg.load_gpr(decoded.rt);
-                    mu.reg_write = Some((decoded.rd, (rs as i32 - rt as i32) as u32));
+                    match (rs as i32).checked_sub(rt as i32) {
+                        Some(val) => mu.reg_write = Some((decoded.rd, val as u32)),
+                        None => self.exception(mu, EXCEPTION_OV),
+                    }
                 }
                 FUNCT_SUBU => {
                     let rs = self.reg.load_gpr(decoded.rs);
@@ -228,6 +457,16 @@ impl CpuSlow {
                     let rt = self.reg.load_gpr(decoded.rt);
                     mu.reg_write = Some((decoded.rd, rs | rt));
                 }
+                FUNCT_XOR => {
+                    let rs = self.reg.load_gpr(decoded.rs);
+                    let rt = self.reg.load_gpr(decoded.rt);
+                    mu.reg_write = Some((decoded.rd, rs ^ rt));
+                }
+                FUNCT_NOR => {
+                    let rs = self.reg.load_gpr(decoded.rs);
+                    let rt = self.reg.load_gpr(decoded.rt);
+                    mu.reg_write = Some((decoded.rd, !(rs | rt)));
+                }
                 FUNCT_SLT => {
                     let rs = self.reg.load_gpr(decoded.rs) as i32;
                     let rt = self.reg.load_gpr(decoded.rt) as i32;
@@ -238,41 +477,25 @@ impl CpuSlow {
                     let rt = self.reg.load_gpr(decoded.rt);
                     mu.reg_write = Some((decoded.rd, if rs < rt { 1 } else { 0 }));
                 }
-                _ => return Err(format!("[SPECIAL] Unknown funct {}", decoded.funct)),
+                _ => self.exception(mu, EXCEPTION_RI),
             },
-            OP_BCOND => match decoded.rt {
-                BCOND_BLTZ => {
-                    let rs = self.reg.load_gpr(decoded.rs);
-                    if (rs as i32) < 0 {
-                        let target = (pc + 4).wrapping_add(decoded.imm_se() << 2);
-                        self.fetch.branch_to(mu, target);
-                    }
-                }
-                BCOND_BGEZ => {
-                    let rs = self.reg.load_gpr(decoded.rs);
-                    if (rs as i32) >= 0 {
-                        let target = (pc + 4).wrapping_add(decoded.imm_se() << 2);
-                        self.fetch.branch_to(mu, target);
-                    }
-                }
-                BCOND_BLTZAL => {
-                    let rs = self.reg.load_gpr(decoded.rs);
-                    if (rs as i32) < 0 {
-                        let target = (pc + 4).wrapping_add(decoded.imm_se() << 2);
-                        mu.reg_write = Some((GPR_RA, pc + 8));
-                        self.fetch.branch_to(mu, target);
-                    }
+            OP_BCOND => {
+                // only bit 0 (BGEZ/BLTZ) and bits 4-1 == 0b1000 (link) are decoded by the R3000A
+                let rs = self.reg.load_gpr(decoded.rs);
+                let taken = if decoded.rt & BCOND_BGEZ != 0 {
+                    (rs as i32) >= 0
+                } else {
+                    (rs as i32) < 0
+                };
+                if decoded.rt & 0x1E == BCOND_BLTZAL {
+                    // $ra is written even when the branch is not taken
+                    mu.reg_write = Some((GPR_RA, pc + 8));
                 }
-                BCOND_BGEZAL => {
-                    let rs = self.reg.load_gpr(decoded.rs);
-                    if (rs as i32) >= 0 {
-                        let target = (pc + 4).wrapping_add(decoded.imm_se() << 2);
-                        mu.reg_write = Some((GPR_RA, pc + 8));
-                        self.fetch.branch_to(mu, target);
-                    }
+                if taken {
+                    let target = (pc + 4).wrapping_add(decoded.imm_se() << 2);
+                    self.fetch.branch_to(mu, target);
                 }
-                _ => return Err(format!("Unknown BCOND rt {}", decoded.rt)),
-            },
+            }
             OP_J => {
                 let target = (pc & 0xF000_0000) | decoded.target;
                 self.fetch.branch_to(mu, target);
@@ -314,7 +537,10 @@ impl CpuSlow {
             }
             OP_ADDI => {
                 let rs = self.reg.load_gpr(decoded.rs);
-                mu.reg_write = Some((decoded.rt, (rs as i32 + decoded.imm_se_i()) as u32));
+                match (rs as i32).checked_add(decoded.imm_se_i()) {
+                    Some(val) => mu.reg_write = Some((decoded.rt, val as u32)),
+                    None => self.exception(mu, EXCEPTION_OV),
+                }
             }
             OP_ADDIU => {
                 let rs = self.reg.load_gpr(decoded.rs);
@@ -343,87 +569,769 @@ impl CpuSlow {
                 let rs = self.reg.load_gpr(decoded.rs);
                 mu.reg_write = Some((decoded.rt, rs | decoded.imm_ze()));
             }
+            OP_XORI => {
+                let rs = self.reg.load_gpr(decoded.rs);
+                mu.reg_write = Some((decoded.rt, rs ^ decoded.imm_ze()));
+            }
             OP_LUI => {
                 mu.reg_write = Some((decoded.rt, decoded.imm_ze() << 16));
             }
             OP_LB => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Byte, false) {
+                    return Ok(());
+                }
                 let val = Bus::lb(m, mu, addr)?;
-                mu.reg_write = Some((decoded.rt, val as i8 as u32));
+                mu.load_write = Some((decoded.rt, val as i8 as u32));
             }
             OP_LH => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Half, false) {
+                    return Ok(());
+                }
                 let val = Bus::lh(m, mu, addr)?;
-                mu.reg_write = Some((decoded.rt, val as i16 as u32));
+                mu.load_write = Some((decoded.rt, val as i16 as u32));
             }
             OP_LW => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Word, false) {
+                    return Ok(());
+                }
                 let val = Bus::lw(m, mu, addr)?;
-                mu.reg_write = Some((decoded.rt, val));
+                mu.load_write = Some((decoded.rt, val));
             }
             OP_LBU => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Byte, false) {
+                    return Ok(());
+                }
                 let val = Bus::lb(m, mu, addr)?;
-                mu.reg_write = Some((decoded.rt, val));
+                mu.load_write = Some((decoded.rt, val));
             }
             OP_LHU => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Half, false) {
+                    return Ok(());
+                }
                 let val = Bus::lh(m, mu, addr)?;
-                mu.reg_write = Some((decoded.rt, val));
+                mu.load_write = Some((decoded.rt, val));
+            }
+            OP_LWL => {
+                let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr & !3, MemOpSize::Word, false) {
+                    return Ok(());
+                }
+                let word = Bus::lw(m, mu, addr & !3)?;
+                let cur = self.load_gpr_bypass(decoded.rt);
+                let val = match addr & 3 {
+                    0 => (cur & 0x00FF_FFFF) | (word << 24),
+                    1 => (cur & 0x0000_FFFF) | (word << 16),
+                    2 => (cur & 0x0000_00FF) | (word << 8),
+                    _ => word,
+                };
+                mu.load_write = Some((decoded.rt, val));
+            }
+            OP_LWR => {
+                let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr & !3, MemOpSize::Word, false) {
+                    return Ok(());
+                }
+                let word = Bus::lw(m, mu, addr & !3)?;
+                let cur = self.load_gpr_bypass(decoded.rt);
+                let val = match addr & 3 {
+                    0 => word,
+                    1 => (cur & 0xFF00_0000) | (word >> 8),
+                    2 => (cur & 0xFFFF_0000) | (word >> 16),
+                    _ => (cur & 0xFFFF_FF00) | (word >> 24),
+                };
+                mu.load_write = Some((decoded.rt, val));
             }
             OP_SB => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Byte, true) {
+                    return Ok(());
+                }
                 let val = self.reg.load_gpr(decoded.rt);
                 mu.bus_write = Some((addr, val, MemOpSize::Byte));
             }
             OP_SH => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Half, true) {
+                    return Ok(());
+                }
                 let val = self.reg.load_gpr(decoded.rt);
                 mu.bus_write = Some((addr, val, MemOpSize::Half));
             }
             OP_SW => {
                 let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr, MemOpSize::Word, true) {
+                    return Ok(());
+                }
                 let val = self.reg.load_gpr(decoded.rt);
                 mu.bus_write = Some((addr, val, MemOpSize::Word));
             }
-            x if x & 0b010000 != 0 => {
-                let copn = x & 0b11;
-                match copn {
-                    0 => match decoded.rs {
+            OP_SWL => {
+                let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr & !3, MemOpSize::Word, true) {
+                    return Ok(());
+                }
+                let val = self.reg.load_gpr(decoded.rt);
+                let aligned = addr & !3;
+                mu.bus_write = Some(match addr & 3 {
+                    0 => (aligned, val >> 24, MemOpSize::Byte),
+                    1 => (aligned, val >> 16, MemOpSize::Half),
+                    2 => {
+                        // three bytes are stored as a halfword and a byte
+                        mu.bus_write_split = Some((aligned + 2, val >> 24, MemOpSize::Byte));
+                        (aligned, val >> 8, MemOpSize::Half)
+                    }
+                    _ => (aligned, val, MemOpSize::Word),
+                });
+            }
+            OP_SWR => {
+                let addr = self.reg.load_gpr(decoded.rs).wrapping_add(decoded.imm_se());
+                if !self.check_access(m, mu, addr & !3, MemOpSize::Word, true) {
+                    return Ok(());
+                }
+                let val = self.reg.load_gpr(decoded.rt);
+                let aligned = addr & !3;
+                mu.bus_write = Some(match addr & 3 {
+                    0 => (aligned, val, MemOpSize::Word),
+                    1 => {
+                        mu.bus_write_split = Some((aligned + 2, val >> 8, MemOpSize::Half));
+                        (aligned + 1, val, MemOpSize::Byte)
+                    }
+                    2 => (aligned + 2, val, MemOpSize::Half),
+                    _ => (aligned + 3, val, MemOpSize::Byte),
+                });
+            }
+            OP_COP0 | OP_COP1 | OP_COP2 | OP_COP3 | OP_LWC0..=OP_LWC3 | OP_SWC0..=OP_SWC3 => {
+                let copn = decoded.opcode & 0b11;
+                if !self.coprocessor_usable(m, copn) {
+                    self.coprocessor_exception(mu, copn);
+                    return Ok(());
+                }
+                match decoded.opcode {
+                    OP_COP0 => match decoded.rs {
                         COP_MT => {
                             let value = self.reg.load_gpr(decoded.rt);
                             mu.cop0_write = Some((decoded.rd, value));
                         }
-                        COP_MF => {
-                            let value = m.cop0.load(decoded.rd)?;
-                            mu.reg_write = Some((decoded.rt, value));
-                        }
+                        COP_MF => match m.cop0.load(decoded.rd) {
+                            Ok(value) => mu.load_write = Some((decoded.rt, value)),
+                            Err(_) => self.exception(mu, EXCEPTION_RI),
+                        },
                         COP_CMD => match decoded.funct {
                             COP0_FUNCT_RFE => mu.exception_return = true,
-                            _ => return Err(format!("Unknown cop0 funct {}", decoded.funct)),
+                            _ => self.exception(mu, EXCEPTION_RI),
                         },
-                        _ => return Err(format!("Unknown cop0 rs {}", decoded.rs)),
+                        _ => self.exception(mu, EXCEPTION_RI),
                     },
-                    _ => return Err(format!("Unsupported cop {}", copn)),
+                    OP_LWC0 | OP_SWC0 => self.exception(mu, EXCEPTION_RI),
+                    // the GTE is not emulated yet, and COP1 and COP3 are not installed,
+                    // enabled accesses do nothing
+                    _ => {}
                 }
             }
-            _ => return Err(format!("Unknown opcode {}", decoded.opcode)),
+            _ => self.exception(mu, EXCEPTION_RI),
         }
 
         Ok(())
     }
 
     pub fn mutate(&mut self, mu: &MachineMutation) {
+        if let Some((reg, val)) = self.load_delay.take() {
+            // a write or load to the same register from the delay slot cancels the pending load
+            let overwritten = |w: Option<(u8, u32)>| matches!(w, Some((idx, _)) if idx == reg);
+            if !overwritten(mu.reg_write) && !overwritten(mu.load_write) {
+                self.reg.store_gpr(reg, val);
+            }
+        }
         if let Some((reg, val)) = mu.reg_write {
             self.reg.store_gpr(reg, val);
         }
+        self.load_delay = mu.load_write;
         if let Some((hi, lo)) = mu.hilo_write {
             self.reg.store_hi(hi);
             self.reg.store_lo(lo);
         }
-        if let Some(tuple) = &mu.icache_write {
-            self.icache.insert(tuple.0, tuple.1.clone());
+        if let Some(ready) = mu.hilo_ready {
+            self.hilo_ready = ready;
+        }
+        if let Some(finish) = mu.write_buffer_push {
+            self.write_buffer.rotate_left(1);
+            self.write_buffer[WRITE_BUFFER_DEPTH - 1] = finish;
+        }
+        if let Some((key, entry)) = &mu.decode_cache_write {
+            // an instruction overwriting itself must not keep its stale decode
+            if !matches!(mu.bus_write, Some((addr, _, _)) if decode_cache_key(addr) == *key) {
+                self.decode_cache.insert(*key, entry.clone());
+            }
         }
         self.fetch.mutate(mu);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::cache_ctrl::CACHE_CTRL_IS1;
+    use crate::core::interrupt::IRQ_VBLANK;
+    use crate::core::ioport::IO_I_MASK;
+    use crate::core::timers::TimerPort;
+
+    fn r_type(rs: u8, rt: u8, rd: u8, funct: u8) -> u32 {
+        ((rs as u32) << 21) | ((rt as u32) << 16) | ((rd as u32) << 11) | funct as u32
+    }
+
+    fn i_type(opcode: u8, rs: u8, rt: u8, imm: u16) -> u32 {
+        ((opcode as u32) << 26) | ((rs as u32) << 21) | ((rt as u32) << 16) | imm as u32
+    }
+
+    fn load_program(program: &[u32]) -> Machine {
+        let mut bios = vec![0; 0x80000];
+        for (i, inst) in program.iter().enumerate() {
+            bios[i * 4..i * 4 + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+        let mut m = Machine::new(bios);
+        m.reset();
+        m
+    }
+
+    // runs `program` from the reset vector, one instruction per cycle
+    fn run(program: &[u32], regs: &[(u8, u32)]) -> Machine {
+        let mut m = load_program(program);
+        for &(idx, val) in regs {
+            m.cpu.reg.store_gpr(idx, val);
+        }
+        for _ in 0..program.len() {
+            m.cycle().expect("cycle failed");
+        }
+        m
+    }
+
+    #[test]
+    fn test_mult() {
+        let m = run(
+            &[r_type(GPR_T0, GPR_T1, 0, FUNCT_MULT)],
+            &[(GPR_T0, (-3i32) as u32), (GPR_T1, 0x4000_0000)],
+        );
+        assert_eq!(m.cpu.reg.load_hi(), 0xFFFF_FFFF);
+        assert_eq!(m.cpu.reg.load_lo(), 0x4000_0000);
+
+        let m = run(
+            &[r_type(GPR_T0, GPR_T1, 0, FUNCT_MULTU)],
+            &[(GPR_T0, 0xFFFF_FFFF), (GPR_T1, 0xFFFF_FFFF)],
+        );
+        assert_eq!(m.cpu.reg.load_hi(), 0xFFFF_FFFE);
+        assert_eq!(m.cpu.reg.load_lo(), 0x0000_0001);
+    }
+
+    #[test]
+    fn test_div_special_cases() {
+        // positive / 0
+        let m = run(&[r_type(GPR_T0, GPR_T1, 0, FUNCT_DIV)], &[(GPR_T0, 5)]);
+        assert_eq!(m.cpu.reg.load_hi(), 5);
+        assert_eq!(m.cpu.reg.load_lo(), 0xFFFF_FFFF);
+        // negative / 0
+        let m = run(
+            &[r_type(GPR_T0, GPR_T1, 0, FUNCT_DIV)],
+            &[(GPR_T0, (-5i32) as u32)],
+        );
+        assert_eq!(m.cpu.reg.load_hi(), (-5i32) as u32);
+        assert_eq!(m.cpu.reg.load_lo(), 1);
+        // 0x80000000 / -1
+        let m = run(
+            &[r_type(GPR_T0, GPR_T1, 0, FUNCT_DIV)],
+            &[(GPR_T0, 0x8000_0000), (GPR_T1, 0xFFFF_FFFF)],
+        );
+        assert_eq!(m.cpu.reg.load_hi(), 0);
+        assert_eq!(m.cpu.reg.load_lo(), 0x8000_0000);
+        // unsigned / 0
+        let m = run(&[r_type(GPR_T0, GPR_T1, 0, FUNCT_DIVU)], &[(GPR_T0, 7)]);
+        assert_eq!(m.cpu.reg.load_hi(), 7);
+        assert_eq!(m.cpu.reg.load_lo(), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn test_logical() {
+        let m = run(
+            &[
+                r_type(GPR_T0, GPR_T1, GPR_T2, FUNCT_XOR),
+                r_type(GPR_T0, GPR_T1, GPR_T3, FUNCT_NOR),
+                i_type(OP_XORI, GPR_T0, GPR_T4, 0xFFFF),
+            ],
+            &[(GPR_T0, 0xFF00_FF00), (GPR_T1, 0x0FF0_0FF0)],
+        );
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T2), 0xF0F0_F0F0);
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T3), 0x000F_000F);
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T4), 0xFF00_00FF);
+    }
+
+    #[test]
+    fn test_bltzal_links_when_not_taken() {
+        let m = run(
+            &[i_type(OP_BCOND, GPR_T0, BCOND_BLTZAL, 0x0010)],
+            &[(GPR_T0, 1)],
+        );
+        assert_eq!(m.cpu.reg.load_gpr(GPR_RA), 0xBFC0_0008);
+        assert_eq!(m.cpu.current_pc(), 0xBFC0_0004);
+    }
+
+    #[test]
+    fn test_break() {
+        let m = run(&[FUNCT_BREAK as u32], &[]);
+        assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+        assert_eq!(m.cop0.load(COP0_CAUSE).unwrap() & 0x7C, (EXCEPTION_BP as u32) << 2);
+    }
+
+    #[test]
+    fn test_load_delay_slot() {
+        let m = run(
+            &[
+                i_type(OP_LW, GPR_T2, GPR_T0, 0x0000),
+                r_type(GPR_T0, GPR_ZERO, GPR_T1, FUNCT_ADDU),
+                r_type(GPR_T0, GPR_ZERO, GPR_T3, FUNCT_ADDU),
+            ],
+            &[(GPR_T0, 0x1234), (GPR_T2, 0xBFC0_0000)],
+        );
+        let loaded = i_type(OP_LW, GPR_T2, GPR_T0, 0x0000);
+        // the delay slot still sees the old value
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T1), 0x1234);
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T3), loaded);
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T0), loaded);
+    }
+
+    #[test]
+    fn test_load_delay_cancelled_by_write() {
+        let m = run(
+            &[
+                i_type(OP_LW, GPR_T2, GPR_T0, 0x0000),
+                i_type(OP_ADDIU, GPR_ZERO, GPR_T0, 0x0005),
+                0,
+            ],
+            &[(GPR_T2, 0xBFC0_0000)],
+        );
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T0), 5);
+    }
+
+    #[test]
+    fn test_load_delay_cancelled_by_load() {
+        // the ADDU sits in the delay slot of the second load and sees neither
+        let program = [
+            i_type(OP_LW, GPR_T2, GPR_T0, 0x0000),
+            i_type(OP_LW, GPR_T2, GPR_T0, 0x0004),
+            r_type(GPR_T0, GPR_ZERO, GPR_T1, FUNCT_ADDU),
+            0,
+        ];
+        let mut m = load_program(&program);
+        m.ram[0x1000..0x1008].copy_from_slice(&[0x11, 0x11, 0x11, 0x11, 0x22, 0x22, 0x22, 0x22]);
+        m.cpu.reg.store_gpr(GPR_T0, 0xCAFE);
+        m.cpu.reg.store_gpr(GPR_T2, 0x8000_1000);
+        for _ in 0..program.len() {
+            m.cycle().unwrap();
+        }
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T1), 0xCAFE);
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T0), 0x2222_2222);
+    }
+
+    #[test]
+    fn test_unaligned_load_merges_with_pending_load() {
+        // LWR + LWL pair reading the unaligned word at 0x80001001
+        let program = [
+            i_type(OP_LWR, GPR_T2, GPR_T0, 0x0001),
+            i_type(OP_LWL, GPR_T2, GPR_T0, 0x0004),
+            0,
+        ];
+        let mut m = load_program(&program);
+        m.ram[0x1000..0x1008].copy_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+        m.cpu.reg.store_gpr(GPR_T0, 0xDEAD_BEEF);
+        m.cpu.reg.store_gpr(GPR_T2, 0x8000_1000);
+        for _ in 0..program.len() {
+            m.cycle().unwrap();
+        }
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T0), 0x4433_2211);
+    }
+
+    #[test]
+    fn test_unaligned_store() {
+        let program = [
+            i_type(OP_SWL, GPR_T2, GPR_T0, 0x0002),
+            i_type(OP_SWR, GPR_T2, GPR_T0, 0x0005),
+        ];
+        let mut m = load_program(&program);
+        m.ram[0x1000..0x1008].fill(0xAA);
+        m.cpu.reg.store_gpr(GPR_T0, 0x4433_2211);
+        m.cpu.reg.store_gpr(GPR_T2, 0x8000_1000);
+        for _ in 0..program.len() {
+            m.cycle().unwrap();
+        }
+        assert_eq!(
+            m.ram[0x1000..0x1008],
+            [0x22, 0x33, 0x44, 0xAA, 0xAA, 0x11, 0x22, 0x33]
+        );
+    }
+
+    #[test]
+    fn test_overflow_exception() {
+        let m = run(
+            &[r_type(GPR_T0, GPR_T1, GPR_T2, FUNCT_ADD)],
+            &[(GPR_T0, 0x7FFF_FFFF), (GPR_T1, 1), (GPR_T2, 0xCAFE)],
+        );
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T2), 0xCAFE);
+        assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_OV as u32) << 2
+        );
+        assert_eq!(m.cop0.load(COP0_EPC).unwrap(), 0xBFC0_0000);
+
+        let m = run(
+            &[r_type(GPR_T0, GPR_T1, GPR_T2, FUNCT_SUB)],
+            &[(GPR_T0, 0x8000_0000), (GPR_T1, 1)],
+        );
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T2), 0);
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_OV as u32) << 2
+        );
+
+        let m = run(
+            &[i_type(OP_ADDI, GPR_T0, GPR_T3, 0xFFFF)],
+            &[(GPR_T0, 0x8000_0000)],
+        );
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T3), 0);
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_OV as u32) << 2
+        );
+    }
+
+    #[test]
+    fn test_address_error() {
+        let m = run(
+            &[i_type(OP_LW, GPR_T0, GPR_T1, 0x0002)],
+            &[(GPR_T0, 0x1F80_0000), (GPR_T1, 0xCAFE)],
+        );
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T1), 0xCAFE);
+        assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_ADEL as u32) << 2
+        );
+        assert_eq!(m.cop0.load(COP0_BADVADDR).unwrap(), 0x1F80_0002);
+
+        let m = run(
+            &[i_type(OP_SH, GPR_T0, GPR_T1, 0x0001)],
+            &[(GPR_T0, 0x1F80_0000)],
+        );
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_ADES as u32) << 2
+        );
+        assert_eq!(m.cop0.load(COP0_BADVADDR).unwrap(), 0x1F80_0001);
+    }
+
+    #[test]
+    fn test_bus_error() {
+        let m = run(
+            &[i_type(OP_LW, GPR_T0, GPR_T1, 0x0000)],
+            &[(GPR_T0, 0x1FA0_0000)],
+        );
+        assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_DBE as u32) << 2
+        );
+
+        // jump to 0xBFA00000
+        let mut m = run(&[(OP_J as u32) << 26 | (0x1FA0_0000 >> 2), 0], &[]);
+        assert_eq!(m.cpu.current_pc(), 0xBFA0_0000);
+        m.cycle().unwrap();
+        assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_IBE as u32) << 2
+        );
+        assert_eq!(m.cop0.load(COP0_EPC).unwrap(), 0xBFA0_0000);
+    }
+
+    #[test]
+    fn test_scratchpad_enable() {
+        // the scratchpad is unmapped until CACHE_CTRL enables it
+        let m = run(
+            &[i_type(OP_LW, GPR_T0, GPR_T1, 0x0000)],
+            &[(GPR_T0, 0x1F80_0000)],
+        );
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_DBE as u32) << 2
+        );
+
+        let program = [
+            i_type(OP_SW, GPR_T2, GPR_T3, 0x0000),
+            i_type(OP_LW, GPR_T2, GPR_T4, 0x0000),
+            i_type(OP_LW, GPR_T0, GPR_T1, 0x0000),
+            0,
+        ];
+        let mut m = load_program(&program);
+        m.dcache[0..4].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        m.cpu.reg.store_gpr(GPR_T0, 0x1F80_0000);
+        m.cpu.reg.store_gpr(GPR_T2, 0xFFFE_0130);
+        m.cpu.reg.store_gpr(GPR_T3, 0x0001_E988);
+        for _ in 0..program.len() {
+            m.cycle().unwrap();
+        }
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T4), 0x0001_E988);
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T1), 0x1234_5678);
+    }
+
+    #[test]
+    fn test_ram_window() {
+        // RAM_SIZE = 1MB + 1MB HighZ + 6MB locked
+        let program = [
+            i_type(OP_SW, GPR_T2, GPR_T3, 0x0060),
+            i_type(OP_LW, GPR_T0, GPR_T1, 0x0000),
+            i_type(OP_LW, GPR_T0, GPR_T4, 0x0004),
+            i_type(OP_LW, GPR_T6, GPR_T5, 0x0000),
+            0,
+        ];
+        let mut m = load_program(&program);
+        m.cpu.reg.store_gpr(GPR_T0, 0x800F_FFFC);
+        m.cpu.reg.store_gpr(GPR_T2, 0x1F80_1000);
+        m.cpu.reg.store_gpr(GPR_T3, 0x0000_0488);
+        m.cpu.reg.store_gpr(GPR_T6, 0x8020_0000);
+        m.ram[0x0F_FFFC..0x10_0000].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        for _ in 0..program.len() - 1 {
+            m.cycle().unwrap();
+        }
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T1), 0x1234_5678);
+        // open bus after the first 1MB
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T4), 0xFFFF_FFFF);
+        // the locked part raises a bus error
+        assert_eq!(
+            m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+            (EXCEPTION_DBE as u32) << 2
+        );
+
+        // the boot setting mirrors 2MB over the whole window
+        let mut m = load_program(&[i_type(OP_LW, GPR_T0, GPR_T1, 0x0000), 0]);
+        m.ram[0..4].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        m.cpu.reg.store_gpr(GPR_T0, 0x8060_0000);
+        m.cycle().unwrap();
+        m.cycle().unwrap();
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T1), 0x1234_5678);
+    }
+
+    #[test]
+    fn test_reserved_instruction() {
+        for inst in [0x3F << 26, 0x0000_0001] {
+            let m = run(&[inst], &[]);
+            assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+            assert_eq!(
+                m.cop0.load(COP0_CAUSE).unwrap() & 0x7C,
+                (EXCEPTION_RI as u32) << 2
+            );
+        }
+    }
+
+    #[test]
+    fn test_coprocessor_unusable() {
+        // MFC1 $t0, $0
+        let m = run(&[i_type(OP_COP1, COP_MF, GPR_T0, 0)], &[]);
+        let cause = m.cop0.load(COP0_CAUSE).unwrap();
+        assert_eq!(cause & 0x7C, (EXCEPTION_CPU as u32) << 2);
+        assert_eq!((cause >> 28) & 3, 1);
+
+        // enter user mode in the delay slot of a jump to RAM, then touch COP0 there
+        let mut m = load_program(&[
+            r_type(GPR_T1, 0, 0, FUNCT_JR),
+            i_type(OP_COP0, COP_MT, GPR_T0, (COP0_STATUS as u16) << 11),
+        ]);
+        let mfc0 = i_type(OP_COP0, COP_MF, GPR_T2, (COP0_STATUS as u16) << 11);
+        m.ram[0x100..0x104].copy_from_slice(&mfc0.to_le_bytes());
+        m.cpu.reg.store_gpr(GPR_T0, 0x0000_0002);
+        m.cpu.reg.store_gpr(GPR_T1, 0x0000_0100);
+        for _ in 0..3 {
+            m.cycle().unwrap();
+        }
+        let cause = m.cop0.load(COP0_CAUSE).unwrap();
+        assert_eq!(cause & 0x7C, (EXCEPTION_CPU as u32) << 2);
+        assert_eq!((cause >> 28) & 3, 0);
+        assert_eq!(m.cop0.load(COP0_EPC).unwrap(), 0x0000_0100);
+
+        // with CU2 set, GTE instructions run as NOPs until the GTE is emulated
+        let m = run(
+            &[
+                i_type(OP_COP0, COP_MT, GPR_T0, (COP0_STATUS as u16) << 11),
+                i_type(OP_COP2, 0x10, 0, 0x0001),
+                i_type(OP_LWC2, GPR_T1, 0, 0),
+            ],
+            &[(GPR_T0, 0x4000_0000), (GPR_T1, 0x8000_0000)],
+        );
+        assert_eq!(m.cpu.current_pc(), 0xBFC0_000C);
+    }
+
+    #[test]
+    fn test_software_interrupt() {
+        let m = run(
+            &[
+                i_type(OP_COP0, COP_MT, GPR_T0, (COP0_STATUS as u16) << 11),
+                i_type(OP_COP0, COP_MT, GPR_T1, (COP0_CAUSE as u16) << 11),
+                0,
+            ],
+            &[(GPR_T0, 0x0000_0101), (GPR_T1, 0x0000_0100)],
+        );
+        assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+        assert_eq!(m.cop0.load(COP0_EPC).unwrap(), 0xBFC0_0008);
+        assert_eq!(m.cop0.load(COP0_CAUSE).unwrap(), 0x0000_0100);
+        // IEc was pushed to IEp
+        assert_eq!(m.cop0.load(COP0_STATUS).unwrap(), 0x0000_0104);
+    }
+
+    #[test]
+    fn test_hardware_interrupt() {
+        let mut m = load_program(&[
+            i_type(OP_COP0, COP_MT, GPR_T0, (COP0_STATUS as u16) << 11),
+            0,
+            0,
+        ]);
+        m.cpu.reg.store_gpr(GPR_T0, 0x0000_0401);
+        m.intc.raise(IRQ_VBLANK);
+        m.cycle().unwrap();
+        m.cycle().unwrap();
+        // masked in I_MASK
+        assert_eq!(m.cpu.current_pc(), 0xBFC0_0008);
+        m.intc.store(IO_I_MASK, 0x0001).unwrap();
+        m.cycle().unwrap();
+        assert_eq!(m.cpu.current_pc(), 0x8000_0080);
+        assert_eq!(m.cop0.load(COP0_EPC).unwrap(), 0xBFC0_0008);
+        assert_eq!(m.cop0.load(COP0_CAUSE).unwrap(), 0x0000_0400);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut m = load_program(&[r_type(GPR_T1, 0, 0, FUNCT_JR), 0]);
+        let ram_program = [
+            i_type(OP_ADDIU, GPR_T0, GPR_T0, 0x0001),
+            // patch the ADDIU above through the uncached mirror
+            i_type(OP_SW, GPR_T3, GPR_T2, 0x0000),
+            r_type(GPR_T1, 0, 0, FUNCT_JR),
+            0,
+        ];
+        for (i, inst) in ram_program.iter().enumerate() {
+            m.ram[0x100 + i * 4..0x104 + i * 4].copy_from_slice(&inst.to_le_bytes());
+        }
+        m.cpu.reg.store_gpr(GPR_T0, 0);
+        m.cpu.reg.store_gpr(GPR_T1, 0x8000_0100);
+        m.cpu
+            .reg
+            .store_gpr(GPR_T2, i_type(OP_ADDIU, GPR_T0, GPR_T0, 0x0010));
+        m.cpu.reg.store_gpr(GPR_T3, 0xA000_0100);
+        for _ in 0..7 {
+            m.cycle().unwrap();
+        }
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T0), 0x11);
+    }
+
+    // places `program` at the start of RAM and jumps there
+    fn load_ram_program(program: &[u32]) -> Machine {
+        let mut m = load_program(&[]);
+        for (i, inst) in program.iter().enumerate() {
+            m.ram[i * 4..i * 4 + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+        m.cpu.fetch.pc = 0x8000_0000;
+        m
+    }
+
+    #[test]
+    fn test_fetch_cycles() {
+        // uncached BIOS fetches pay the 8-bit ROM wait states
+        let mut m = load_program(&[0, 0]);
+        m.cycle().unwrap();
+        assert_eq!(m.clock, 25);
+
+        // RAM is much faster
+        let mut m = load_ram_program(&[0, 0]);
+        m.cycle().unwrap();
+        assert_eq!(m.clock, 5);
+    }
+
+    #[test]
+    fn test_icache_write_through() {
+        let patched = i_type(OP_ADDIU, GPR_ZERO, GPR_T2, 2);
+        let mut m = load_ram_program(&[
+            i_type(OP_SW, GPR_T0, GPR_T1, 0x0008),
+            0,
+            i_type(OP_ADDIU, GPR_ZERO, GPR_T2, 1),
+        ]);
+        m.cache_ctrl.store(CACHE_CTRL_IS1);
+        m.icache.configure(&m.cache_ctrl);
+        m.cpu.reg.store_gpr(GPR_T0, 0x8000_0000);
+        m.cpu.reg.store_gpr(GPR_T1, patched);
+        for _ in 0..3 {
+            m.cycle().unwrap();
+        }
+        // the store reaches RAM, the line filled by the first fetch keeps the old code
+        assert_eq!(m.ram[8..12], patched.to_le_bytes());
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T2), 1);
+    }
+
+    #[test]
+    fn test_timer_flags_during_mode_read() {
+        // LW of the sysclock timer mode, the target is reached during the uncached fetch
+        let mut m = load_program(&[i_type(OP_LW, GPR_T0, GPR_T1, 0x1124), 0]);
+        m.cpu.reg.store_gpr(GPR_T0, 0x1F80_0000);
+        m.timers.sysclock.write_mode(0x0008);
+        m.timers.sysclock.write_target(2);
+        m.cycle().unwrap();
+        // reached target flag
+        assert_ne!(m.timers.peek(m.clock).sysclock.read_mode() & 0x1000, 0);
+        m.cycle().unwrap();
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T1) & 0x1000, 0);
+    }
+
+    #[test]
+    fn test_hilo_interlock() {
+        let mut m = load_ram_program(&[
+            r_type(GPR_T0, GPR_T1, 0, FUNCT_MULT),
+            r_type(0, 0, GPR_T2, FUNCT_MFLO),
+            0,
+        ]);
+        m.cpu.reg.store_gpr(GPR_T0, 0x0010_0000);
+        m.cpu.reg.store_gpr(GPR_T1, 3);
+        m.cycle().unwrap();
+        assert_eq!(m.clock, 5);
+        // the result is ready 13 clocks after the MULT issued
+        m.cycle().unwrap();
+        assert_eq!(m.clock, 18);
+        assert_eq!(m.cpu.reg.load_gpr(GPR_T2), 0x0030_0000);
+
+        // -0x100000 has the significant bits of 0xFFFFF
+        let mut m = load_ram_program(&[
+            r_type(GPR_T0, GPR_T1, 0, FUNCT_MULT),
+            r_type(0, 0, GPR_T2, FUNCT_MFLO),
+            0,
+        ]);
+        m.cpu.reg.store_gpr(GPR_T0, 0xFFF0_0000);
+        m.cpu.reg.store_gpr(GPR_T1, 3);
+        m.cycle().unwrap();
+        m.cycle().unwrap();
+        assert_eq!(m.clock, 14);
+    }
+
+    #[test]
+    fn test_write_buffer_stall() {
+        // stores to the slow BIOS ROM fill the 4-entry write buffer
+        let program = [i_type(OP_SW, GPR_T0, GPR_ZERO, 0x0100); 5];
+        let mut m = load_ram_program(&program);
+        m.cpu.reg.store_gpr(GPR_T0, 0xBFC0_0000);
+        for _ in 0..4 {
+            m.cycle().unwrap();
+        }
+        assert_eq!(m.clock, 20);
+        // the fifth store waits for the first one to retire at clock 30
+        m.cycle().unwrap();
+        assert_eq!(m.clock, 30);
+    }
+}
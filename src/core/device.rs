@@ -0,0 +1,237 @@
+use std::any::Any;
+
+use serde::{Deserialize, Serialize};
+
+use super::MemOpSize;
+
+// index of an attached device, in attachment order
+pub type DeviceId = usize;
+
+// the physical address space, mirrored into KUSEG, KSEG0 and KSEG1
+pub const DEVICE_PHYS_LIMIT: u32 = 0x2000_0000;
+pub const DEVICE_MAX: usize = 32;
+
+// hardware attached to the memory bus from outside the crate
+pub trait BusDevice: Any {
+    // identifies the device in save states
+    fn name(&self) -> &str;
+
+    // offsets are relative to the attached base, loads must not have side effects
+    fn load(&self, offset: u32, size: MemOpSize) -> Result<u32, String>;
+    fn store(&mut self, offset: u32, val: u32, size: MemOpSize) -> Result<(), String>;
+
+    // applied after the instruction that performed the load, e.g. to pop a FIFO
+    fn commit_load(&mut self, _offset: u32, _size: MemOpSize) {}
+
+    // wait states of an access
+    fn access_time(&self, _offset: u32, _size: MemOpSize) -> u32 {
+        0
+    }
+
+    fn reset(&mut self) {}
+
+    fn save_state(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub struct AttachedDevice {
+    pub base: u32,
+    pub size: u32,
+    pub device: Box<dyn BusDevice>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeviceState {
+    pub name: String,
+    pub base: u32,
+    pub state: Vec<u8>,
+}
+
+pub struct Devices {
+    attached: Vec<AttachedDevice>,
+}
+
+impl Devices {
+    pub fn new() -> Devices {
+        Devices { attached: vec![] }
+    }
+
+    pub fn attach(
+        &mut self,
+        base: u32,
+        size: u32,
+        device: Box<dyn BusDevice>,
+    ) -> Result<DeviceId, String> {
+        if size == 0 || base as u64 + size as u64 > DEVICE_PHYS_LIMIT as u64 {
+            return Err(format!(
+                "Invalid device range 0x{:08X}+0x{:X} for {}",
+                base,
+                size,
+                device.name()
+            ));
+        }
+        if self.attached.len() >= DEVICE_MAX {
+            return Err(format!("Too many devices attached, {}", device.name()));
+        }
+        if let Some(other) = self
+            .attached
+            .iter()
+            .find(|other| base < other.base + other.size && other.base < base + size)
+        {
+            return Err(format!(
+                "{} at 0x{:08X} overlaps {} at 0x{:08X}",
+                device.name(),
+                base,
+                other.device.name(),
+                other.base
+            ));
+        }
+        self.attached.push(AttachedDevice { base, size, device });
+        Ok(self.attached.len() - 1)
+    }
+
+    pub fn ranges(&self) -> Vec<(u32, u32)> {
+        self.attached.iter().map(|d| (d.base, d.size)).collect()
+    }
+
+    pub fn get(&self, id: DeviceId) -> Option<&dyn BusDevice> {
+        self.attached.get(id).map(|d| d.device.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: DeviceId) -> Option<&mut dyn BusDevice> {
+        self.attached.get_mut(id).map(|d| d.device.as_mut())
+    }
+
+    pub fn reset(&mut self) {
+        for attached in self.attached.iter_mut() {
+            attached.device.reset();
+        }
+    }
+
+    pub fn save_state(&self) -> Vec<DeviceState> {
+        self.attached
+            .iter()
+            .map(|attached| DeviceState {
+                name: attached.device.name().to_string(),
+                base: attached.base,
+                state: attached.device.save_state(),
+            })
+            .collect()
+    }
+
+    // devices are matched by name and base, states of devices no longer attached are dropped
+    pub fn load_state(&mut self, states: &[DeviceState]) -> Result<(), String> {
+        for attached in self.attached.iter_mut() {
+            match states
+                .iter()
+                .find(|s| s.name == attached.device.name() && s.base == attached.base)
+            {
+                Some(state) => attached.device.load_state(&state.state)?,
+                None => println!(
+                    "WARN: No saved state for device {} at 0x{:08X}",
+                    attached.device.name(),
+                    attached.base
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{bus::Bus, Machine, MachineMutation};
+
+    // test harness mailbox: words written are queued, reads at +4 pop them
+    struct Mailbox {
+        queue: Vec<u32>,
+    }
+
+    impl BusDevice for Mailbox {
+        fn name(&self) -> &str {
+            "mailbox"
+        }
+
+        fn load(&self, offset: u32, _: MemOpSize) -> Result<u32, String> {
+            match offset {
+                0 => Ok(self.queue.len() as u32),
+                4 => Ok(self.queue.first().copied().unwrap_or(0)),
+                _ => Err(format!("mailbox: read at 0x{:X}", offset)),
+            }
+        }
+
+        fn store(&mut self, offset: u32, val: u32, _: MemOpSize) -> Result<(), String> {
+            match offset {
+                0 => self.queue.push(val),
+                _ => return Err(format!("mailbox: write at 0x{:X}", offset)),
+            }
+            Ok(())
+        }
+
+        fn commit_load(&mut self, offset: u32, _: MemOpSize) {
+            if offset == 4 && !self.queue.is_empty() {
+                self.queue.remove(0);
+            }
+        }
+
+        fn reset(&mut self) {
+            self.queue.clear();
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            self.queue.iter().flat_map(|w| w.to_le_bytes()).collect()
+        }
+
+        fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+            self.queue = state
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect();
+            Ok(())
+        }
+    }
+
+    fn store(m: &mut Machine, addr: u32, val: u32) {
+        let mut mu = MachineMutation::new();
+        mu.bus_write = Some((addr, val, MemOpSize::Word));
+        Bus::mutate(m, &mut mu).unwrap();
+    }
+
+    fn load(m: &mut Machine, addr: u32) -> u32 {
+        let mut mu = MachineMutation::new();
+        let val = Bus::lw(m, &mut mu, addr).unwrap();
+        m.commit_device_load(&mu);
+        val
+    }
+
+    #[test]
+    fn test_mailbox_device() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        let id = m
+            .attach_device(0x1FA0_0000, 0x10, Box::new(Mailbox { queue: vec![] }))
+            .unwrap();
+        assert!(m
+            .attach_device(0x1FA0_0008, 0x10, Box::new(Mailbox { queue: vec![] }))
+            .is_err());
+
+        // mirrored in every segment
+        store(&mut m, 0xBFA0_0000, 0x1234);
+        store(&mut m, 0x1FA0_0000, 0x5678);
+        assert_eq!(load(&mut m, 0x9FA0_0000), 2);
+        assert_eq!(load(&mut m, 0xBFA0_0004), 0x1234);
+        assert_eq!(m.device::<Mailbox>(id).unwrap().queue, vec![0x5678]);
+        assert!(m.device::<Mailbox>(id + 1).is_none());
+
+        let state = m.save_state();
+        m.reset();
+        assert_eq!(load(&mut m, 0xBFA0_0000), 0);
+        m.load_state(state).unwrap();
+        assert_eq!(load(&mut m, 0xBFA0_0004), 0x5678);
+    }
+}
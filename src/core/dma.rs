@@ -0,0 +1,592 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ioport::*, mem_load_u32, mem_store_u32, scheduler::SchedulerEvent, Machine, IRQ_DMA, IRQ_GPU,
+};
+
+pub const DMA_MDEC_IN: usize = 0;
+pub const DMA_MDEC_OUT: usize = 1;
+pub const DMA_GPU: usize = 2;
+pub const DMA_CDROM: usize = 3;
+pub const DMA_SPU: usize = 4;
+pub const DMA_PIO: usize = 5;
+pub const DMA_OTC: usize = 6;
+const DMA_CHANNELS: usize = 7;
+
+// D_CHCR
+const CHCR_TO_DEVICE: u32 = 0x0000_0001;
+const CHCR_STEP_BACK: u32 = 0x0000_0002;
+const CHCR_CHOPPING: u32 = 0x0000_0100;
+const CHCR_SYNC_SHIFT: u32 = 9;
+const CHCR_BUSY: u32 = 0x0100_0000;
+const CHCR_TRIGGER: u32 = 0x1000_0000;
+const CHCR_WRITE_MASK: u32 = 0x7177_0703;
+// OTC only has the start bits, and always runs backwards to RAM in manual mode
+const CHCR_OTC_WRITE_MASK: u32 = 0x5100_0000;
+
+// written by OTC to the first entry of the table
+const OTC_END: u32 = 0x00FF_FFFF;
+
+// DICR
+const DICR_WRITE_MASK: u32 = 0x00FF_803F;
+const DICR_FORCE_IRQ: u32 = 0x0000_8000;
+const DICR_ENABLE_SHIFT: u32 = 16;
+const DICR_MASTER_ENABLE: u32 = 0x0080_0000;
+const DICR_FLAG_SHIFT: u32 = 24;
+const DICR_FLAGS: u32 = 0x7F00_0000;
+const DICR_MASTER_FLAG: u32 = 0x8000_0000;
+
+const DPCR_INIT: u32 = 0x0765_4321;
+
+// DMA only addresses the 2MB of RAM, in words
+const DMA_ADDR_MASK: u32 = 0x001F_FFFC;
+// next pointer of the last linked list node
+const LINKED_LIST_END: u32 = 0x0080_0000;
+// one node per word of RAM, longer lists must loop
+const LINKED_LIST_MAX_NODES: u32 = 0x8_0000;
+
+// bus clocks per word moved
+const DMA_WORD_CYCLES: u64 = 1;
+// device request handshake before each block or linked list node
+const DMA_BLOCK_CYCLES: u64 = 1;
+
+fn channel_name(ch: usize) -> &'static str {
+    match ch {
+        DMA_MDEC_IN => "MDEC_IN",
+        DMA_MDEC_OUT => "MDEC_OUT",
+        DMA_GPU => "GPU",
+        DMA_CDROM => "CDROM",
+        DMA_SPU => "SPU",
+        DMA_PIO => "PIO",
+        DMA_OTC => "OTC",
+        _ => "?",
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SyncMode {
+    // the whole BCR word count at once, started by the trigger bit
+    Manual,
+    // BCR blocks, each on a request from the device
+    Request,
+    // GPU command lists chained through headers in RAM
+    LinkedList,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct DmaChannel {
+    madr: u32,
+    bcr: u32,
+    chcr: u32,
+}
+
+impl DmaChannel {
+    fn new() -> DmaChannel {
+        DmaChannel {
+            madr: 0,
+            bcr: 0,
+            chcr: 0,
+        }
+    }
+
+    fn sync_mode(&self) -> SyncMode {
+        match (self.chcr >> CHCR_SYNC_SHIFT) & 3 {
+            0 => SyncMode::Manual,
+            1 => SyncMode::Request,
+            // mode 3 is reserved and behaves like a linked list on hardware
+            _ => SyncMode::LinkedList,
+        }
+    }
+
+    fn reads_ram(&self) -> bool {
+        self.chcr & CHCR_TO_DEVICE != 0
+    }
+
+    fn step(&self) -> u32 {
+        if self.chcr & CHCR_STEP_BACK != 0 {
+            (-4i32) as u32
+        } else {
+            4
+        }
+    }
+
+    fn manual_words(&self) -> u32 {
+        match self.bcr & 0xFFFF {
+            0 => 0x1_0000,
+            words => words,
+        }
+    }
+
+    // manual transfers hold the bus until done, unless chopped into windows
+    fn stalls_cpu(&self) -> bool {
+        self.sync_mode() == SyncMode::Manual && self.chcr & CHCR_CHOPPING == 0
+    }
+
+    // manual transfers also wait for the trigger bit
+    fn active(&self) -> bool {
+        self.chcr & CHCR_BUSY != 0
+            && (self.sync_mode() != SyncMode::Manual || self.chcr & CHCR_TRIGGER != 0)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Dma {
+    channels: [DmaChannel; DMA_CHANNELS],
+    dpcr: u32,
+    dicr: u32,
+    // completion clock of the transfers running alongside the CPU
+    in_flight: [Option<u64>; DMA_CHANNELS],
+    // clock the last queued transfer releases the bus
+    bus_free: u64,
+}
+
+impl Dma {
+    pub fn new() -> Dma {
+        Dma {
+            channels: [DmaChannel::new(); DMA_CHANNELS],
+            dpcr: DPCR_INIT,
+            dicr: 0,
+            in_flight: [None; DMA_CHANNELS],
+            bus_free: 0,
+        }
+    }
+
+    pub fn load(&self, addr: u32) -> Result<u32, String> {
+        match addr {
+            IO_DMA_DPCR => Ok(self.dpcr),
+            IO_DMA_DICR => Ok(self.dicr_value()),
+            IO_DMA_MDEC_IN_MADR..=IO_DMA_OTC_CHCR => {
+                let channel = &self.channels[((addr - IO_DMA_MDEC_IN_MADR) >> 4) as usize];
+                match addr & 0x0F {
+                    0x0 => Ok(channel.madr),
+                    0x4 => Ok(channel.bcr),
+                    0x8 => Ok(channel.chcr),
+                    _ => Err(format!("Unhandled DMA read at 0x{:08X}", addr)),
+                }
+            }
+            _ => Err(format!("Unhandled DMA read at 0x{:08X}", addr)),
+        }
+    }
+
+    pub fn store(&mut self, addr: u32, val: u32) -> Result<(), String> {
+        match addr {
+            IO_DMA_DPCR => self.dpcr = val,
+            // the flags are acknowledged by writing 1
+            IO_DMA_DICR => {
+                self.dicr =
+                    (self.dicr & !DICR_WRITE_MASK & !(val & DICR_FLAGS)) | (val & DICR_WRITE_MASK);
+            }
+            IO_DMA_MDEC_IN_MADR..=IO_DMA_OTC_CHCR => {
+                let ch = ((addr - IO_DMA_MDEC_IN_MADR) >> 4) as usize;
+                let channel = &mut self.channels[ch];
+                match addr & 0x0F {
+                    0x0 => channel.madr = val & 0x00FF_FFFF,
+                    0x4 => channel.bcr = val,
+                    0x8 if ch == DMA_OTC => {
+                        channel.chcr = (val & CHCR_OTC_WRITE_MASK) | CHCR_STEP_BACK
+                    }
+                    0x8 => channel.chcr = val & CHCR_WRITE_MASK,
+                    _ => return Err(format!("Unhandled DMA write at 0x{:08X}", addr)),
+                }
+            }
+            _ => return Err(format!("Unhandled DMA write at 0x{:08X}", addr)),
+        }
+        Ok(())
+    }
+
+    // DICR without the flags, writing it back changes nothing
+    pub fn dicr_control(&self) -> u32 {
+        self.dicr & !DICR_FLAGS
+    }
+
+    fn dicr_value(&self) -> u32 {
+        if self.irq_line() {
+            self.dicr | DICR_MASTER_FLAG
+        } else {
+            self.dicr
+        }
+    }
+
+    pub fn irq_line(&self) -> bool {
+        let enabled = (self.dicr >> DICR_ENABLE_SHIFT) & 0x7F;
+        let flags = (self.dicr >> DICR_FLAG_SHIFT) & 0x7F;
+        self.dicr & DICR_FORCE_IRQ != 0
+            || (self.dicr & DICR_MASTER_ENABLE != 0 && enabled & flags != 0)
+    }
+
+    // DPCR holds a 3-bit priority and an enable bit per channel
+    fn priority(&self, ch: usize) -> Option<u32> {
+        let field = self.dpcr >> (4 * ch);
+        if field & 0x8 != 0 {
+            Some(field & 0x7)
+        } else {
+            None
+        }
+    }
+
+    // the next channel to run, lower priority values first and the higher channel on a tie
+    fn next_channel(&self) -> Option<usize> {
+        (0..DMA_CHANNELS)
+            .rev()
+            .filter(|&ch| self.channels[ch].active() && self.in_flight[ch].is_none())
+            .filter_map(|ch| self.priority(ch).map(|prio| (prio, ch)))
+            .min_by_key(|&(prio, _)| prio)
+            .map(|(_, ch)| ch)
+    }
+
+    fn complete(&mut self, ch: usize) {
+        self.channels[ch].chcr &= !(CHCR_BUSY | CHCR_TRIGGER);
+        if self.dicr & (1 << (DICR_ENABLE_SHIFT + ch as u32)) != 0 {
+            self.dicr |= 1 << (DICR_FLAG_SHIFT + ch as u32);
+        }
+    }
+
+    // starts the channels enabled by a register write, one bus master at a time
+    pub fn run(m: &mut Machine) {
+        // the CPU cleared the busy bit of a transfer in progress
+        for ch in 0..DMA_CHANNELS {
+            if !m.dma.channels[ch].active() {
+                m.dma.in_flight[ch] = None;
+            }
+        }
+        while let Some(ch) = m.dma.next_channel() {
+            let start = m.clock.max(m.dma.bus_free);
+            let finish = start + Dma::transfer_cycles(m, ch);
+            m.dma.bus_free = finish;
+            if m.dma.channels[ch].stalls_cpu() {
+                // the CPU is stopped until the bus is released
+                m.clock = finish;
+                Dma::finish(m, ch);
+            } else {
+                m.dma.in_flight[ch] = Some(finish);
+            }
+        }
+        Dma::schedule(m);
+    }
+
+    // completes the transfers due, from the scheduler
+    pub fn complete_due(m: &mut Machine) {
+        while let Some(ch) = (0..DMA_CHANNELS)
+            .filter(|&ch| matches!(m.dma.in_flight[ch], Some(at) if at <= m.clock))
+            .min_by_key(|&ch| m.dma.in_flight[ch])
+        {
+            m.dma.in_flight[ch] = None;
+            Dma::finish(m, ch);
+        }
+        Dma::schedule(m);
+    }
+
+    fn schedule(m: &mut Machine) {
+        match m.dma.in_flight.iter().flatten().min() {
+            Some(&at) => m.scheduler.schedule(SchedulerEvent::Dma, at),
+            None => m.scheduler.cancel(SchedulerEvent::Dma),
+        }
+        m.intc.set_line(IRQ_DMA, m.dma.irq_line());
+    }
+
+    fn finish(m: &mut Machine, ch: usize) {
+        Dma::transfer(m, ch);
+        m.dma.complete(ch);
+    }
+
+    fn transfer_cycles(m: &Machine, ch: usize) -> u64 {
+        let channel = m.dma.channels[ch];
+        match channel.sync_mode() {
+            SyncMode::Manual => {
+                let words = channel.manual_words() as u64;
+                if channel.chcr & CHCR_CHOPPING == 0 {
+                    return words * DMA_WORD_CYCLES;
+                }
+                // the CPU gets the bus for its window after each DMA window
+                let dma_window = 1u64 << ((channel.chcr >> 16) & 7);
+                let cpu_window = 1u64 << ((channel.chcr >> 20) & 7);
+                words * DMA_WORD_CYCLES + (words.div_ceil(dma_window) - 1) * cpu_window
+            }
+            SyncMode::Request => {
+                let size = (channel.bcr & 0xFFFF) as u64;
+                let blocks = (channel.bcr >> 16) as u64;
+                blocks * (DMA_BLOCK_CYCLES + size * DMA_WORD_CYCLES)
+            }
+            SyncMode::LinkedList => {
+                let mut cycles = 0;
+                let mut addr = channel.madr & DMA_ADDR_MASK;
+                for _ in 0..LINKED_LIST_MAX_NODES {
+                    let header = Dma::ram_load(m, addr);
+                    cycles += DMA_BLOCK_CYCLES + (header >> 24) as u64 * DMA_WORD_CYCLES;
+                    if header & LINKED_LIST_END != 0 {
+                        break;
+                    }
+                    addr = header & DMA_ADDR_MASK;
+                }
+                cycles
+            }
+        }
+    }
+
+    fn transfer(m: &mut Machine, ch: usize) {
+        let channel = m.dma.channels[ch];
+        // only the GPU is connected, other devices read as 0 and drop writes
+        if ch != DMA_OTC && ch != DMA_GPU {
+            println!(
+                "WARN: DMA_{} transfer with an unconnected device",
+                channel_name(ch)
+            );
+        }
+        match channel.sync_mode() {
+            // MADR is left at the start address
+            SyncMode::Manual => {
+                let words = channel.manual_words();
+                if ch == DMA_OTC {
+                    Dma::transfer_otc(m, channel.madr, words);
+                } else {
+                    Dma::transfer_block(m, ch, channel.madr, words);
+                }
+            }
+            SyncMode::Request => {
+                let size = channel.bcr & 0xFFFF;
+                let blocks = channel.bcr >> 16;
+                let mut addr = channel.madr;
+                for _ in 0..blocks {
+                    addr = Dma::transfer_block(m, ch, addr, size);
+                }
+                m.dma.channels[ch].madr = addr & 0x00FF_FFFF;
+                m.dma.channels[ch].bcr &= 0xFFFF;
+            }
+            SyncMode::LinkedList => {
+                if !channel.reads_ram() {
+                    println!("WARN: DMA_{} linked list from device", channel_name(ch));
+                    return;
+                }
+                let mut addr = channel.madr & DMA_ADDR_MASK;
+                for _ in 0..LINKED_LIST_MAX_NODES {
+                    let header = Dma::ram_load(m, addr);
+                    for i in 1..=header >> 24 {
+                        let val = Dma::ram_load(m, addr + 4 * i);
+                        Dma::port_write(m, ch, val);
+                    }
+                    let next = header & 0x00FF_FFFF;
+                    m.dma.channels[ch].madr = next;
+                    if next & LINKED_LIST_END != 0 {
+                        return;
+                    }
+                    addr = next & DMA_ADDR_MASK;
+                }
+                println!(
+                    "WARN: DMA_{} linked list did not end after {} nodes",
+                    channel_name(ch),
+                    LINKED_LIST_MAX_NODES
+                );
+            }
+        }
+    }
+
+    // returns the address following the block
+    fn transfer_block(m: &mut Machine, ch: usize, addr: u32, words: u32) -> u32 {
+        let channel = m.dma.channels[ch];
+        let mut addr = addr;
+        for _ in 0..words {
+            if channel.reads_ram() {
+                let val = Dma::ram_load(m, addr);
+                Dma::port_write(m, ch, val);
+            } else {
+                let val = Dma::port_read(m, ch);
+                Dma::ram_store(m, addr, val);
+            }
+            addr = addr.wrapping_add(channel.step());
+        }
+        addr
+    }
+
+    // links each entry of the ordering table to the previous one, the lowest gets the terminator
+    fn transfer_otc(m: &mut Machine, addr: u32, words: u32) {
+        let mut addr = addr;
+        for i in (0..words).rev() {
+            let next = if i == 0 {
+                OTC_END
+            } else {
+                addr.wrapping_sub(4) & DMA_ADDR_MASK
+            };
+            Dma::ram_store(m, addr, next);
+            addr = addr.wrapping_sub(4);
+        }
+    }
+
+    fn ram_load(m: &Machine, addr: u32) -> u32 {
+        mem_load_u32(&m.ram, addr & DMA_ADDR_MASK & m.memctrl.ram_mask())
+    }
+
+    fn ram_store(m: &mut Machine, addr: u32, val: u32) {
+        let addr = addr & DMA_ADDR_MASK & m.memctrl.ram_mask();
+        mem_store_u32(&mut m.ram, addr, val);
+        m.cpu.invalidate_decoded(addr);
+    }
+
+    fn port_read(m: &mut Machine, ch: usize) -> u32 {
+        if ch == DMA_GPU {
+            let val = m.gpu.read();
+            m.gpu.commit_read();
+            val
+        } else {
+            0
+        }
+    }
+
+    fn port_write(m: &mut Machine, ch: usize, val: u32) {
+        if ch == DMA_GPU {
+            m.gpu.gp0(val);
+            m.intc.set_line(IRQ_GPU, m.gpu.irq_line());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dicr_irq() {
+        let mut dma = Dma::new();
+        // enable channel 2's IRQ and the master enable
+        dma.store(IO_DMA_DICR, 0x0084_0000).unwrap();
+        dma.store(IO_DMA_DPCR, DPCR_INIT | 0x0000_0800).unwrap();
+        dma.channels[DMA_GPU].chcr = CHCR_BUSY | CHCR_TRIGGER;
+        assert_eq!(dma.next_channel(), Some(DMA_GPU));
+        dma.complete(DMA_GPU);
+        assert_eq!(dma.load(IO_DMA_DICR).unwrap(), 0x8484_0000);
+        // acknowledge the flag
+        dma.store(IO_DMA_DICR, 0x0484_0000).unwrap();
+        assert_eq!(dma.load(IO_DMA_DICR).unwrap(), 0x0084_0000);
+    }
+
+    #[test]
+    fn test_channel_priority() {
+        let mut dma = Dma::new();
+        for ch in [DMA_MDEC_IN, DMA_GPU, DMA_SPU] {
+            dma.channels[ch].chcr = CHCR_BUSY | (1 << CHCR_SYNC_SHIFT);
+        }
+        // disabled channels never run
+        assert_eq!(dma.next_channel(), None);
+        dma.store(IO_DMA_DPCR, 0x0009_0B0B).unwrap();
+        assert_eq!(dma.next_channel(), Some(DMA_SPU));
+        dma.complete(DMA_SPU);
+        // equal priorities go to the higher channel
+        assert_eq!(dma.next_channel(), Some(DMA_GPU));
+    }
+
+    fn run_otc(m: &mut Machine, madr: u32, bcr: u32) {
+        m.dma.store(IO_DMA_DPCR, DPCR_INIT | 0x0800_0000).unwrap();
+        m.dma.store(IO_DMA_OTC_MADR, madr).unwrap();
+        m.dma.store(IO_DMA_OTC_BCR, bcr).unwrap();
+        m.dma
+            .store(IO_DMA_OTC_CHCR, CHCR_BUSY | CHCR_TRIGGER)
+            .unwrap();
+        Dma::run(m);
+    }
+
+    fn ram_words(m: &Machine, addr: u32, words: u32) -> Vec<u32> {
+        (0..words)
+            .map(|i| mem_load_u32(&m.ram, addr + 4 * i))
+            .collect()
+    }
+
+    #[test]
+    fn test_otc_table() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        m.ram[0x10_0000..0x10_0020].fill(0xAA);
+        run_otc(&mut m, 0x8010_0010, 4);
+        assert_eq!(
+            ram_words(&m, 0x10_0000, 7),
+            [
+                0xAAAA_AAAA,
+                OTC_END,
+                0x0010_0004,
+                0x0010_0008,
+                0x0010_000C,
+                0xAAAA_AAAA,
+                0xAAAA_AAAA
+            ]
+        );
+        // manual mode leaves MADR and BCR alone
+        assert_eq!(m.dma.load(IO_DMA_OTC_MADR).unwrap(), 0x0010_0010);
+        assert_eq!(m.dma.load(IO_DMA_OTC_BCR).unwrap(), 4);
+        assert_eq!(m.dma.load(IO_DMA_OTC_CHCR).unwrap(), CHCR_STEP_BACK);
+    }
+
+    #[test]
+    fn test_otc_wraps_below_zero() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        run_otc(&mut m, 0x0000_0004, 3);
+        assert_eq!(ram_words(&m, 0, 2), [0x001F_FFFC, 0x0000_0000]);
+        assert_eq!(ram_words(&m, 0x1F_FFFC, 1), [OTC_END]);
+    }
+
+    #[test]
+    fn test_otc_forced_settings() {
+        let mut dma = Dma::new();
+        // direction, sync mode, chopping and forward steps are ignored
+        dma.store(IO_DMA_OTC_CHCR, 0x7177_0701).unwrap();
+        assert_eq!(dma.load(IO_DMA_OTC_CHCR).unwrap(), 0x5100_0002);
+        assert_eq!(dma.channels[DMA_OTC].sync_mode(), SyncMode::Manual);
+        assert!(!dma.channels[DMA_OTC].reads_ram());
+    }
+
+    #[test]
+    fn test_block_transfer_stalls_cpu() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        m.scheduler.cancel(SchedulerEvent::VBlank);
+        m.clock = 100;
+        run_otc(&mut m, 0x0010_0000, 16);
+        assert_eq!(m.clock, 116);
+        assert_eq!(m.scheduler.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_async_transfers_share_the_bus() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        // only the DMA events are left
+        m.scheduler.cancel(SchedulerEvent::VBlank);
+        m.ram[0x1000..0x1040].fill(0xAA);
+        m.dma.store(IO_DMA_DPCR, DPCR_INIT | 0x0008_0008).unwrap();
+        // 4 windows of 4 words, with 8 clocks for the CPU in between
+        m.dma.store(IO_DMA_SPU_MADR, 0x1000).unwrap();
+        m.dma.store(IO_DMA_SPU_BCR, 16).unwrap();
+        m.dma.store(IO_DMA_SPU_CHCR, 0x1132_0100).unwrap();
+        Dma::run(&mut m);
+        // 2 blocks of 4 words, queued behind the SPU transfer
+        m.dma.store(IO_DMA_MDEC_IN_MADR, 0x2000).unwrap();
+        m.dma.store(IO_DMA_MDEC_IN_BCR, 0x0002_0004).unwrap();
+        m.dma.store(IO_DMA_MDEC_IN_CHCR, 0x0100_0201).unwrap();
+        Dma::run(&mut m);
+
+        assert_eq!(m.clock, 0);
+        assert_eq!(m.scheduler.next_deadline(), Some(40));
+        assert_ne!(m.dma.load(IO_DMA_SPU_CHCR).unwrap() & CHCR_BUSY, 0);
+        assert_eq!(ram_words(&m, 0x1000, 1), [0xAAAA_AAAA]);
+
+        m.clock = 40;
+        Dma::complete_due(&mut m);
+        assert_eq!(m.dma.load(IO_DMA_SPU_CHCR).unwrap() & CHCR_BUSY, 0);
+        assert_eq!(ram_words(&m, 0x1000, 1), [0]);
+        assert_eq!(m.scheduler.next_deadline(), Some(50));
+        assert_ne!(m.dma.load(IO_DMA_MDEC_IN_CHCR).unwrap() & CHCR_BUSY, 0);
+        m.clock = 50;
+        Dma::complete_due(&mut m);
+        assert_eq!(m.dma.load(IO_DMA_MDEC_IN_MADR).unwrap(), 0x2020);
+        assert_eq!(m.scheduler.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_circular_linked_list() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        // a node without words pointing back to itself
+        m.ram[0x1000..0x1004].copy_from_slice(&0x0000_1000u32.to_le_bytes());
+        m.dma.store(IO_DMA_DPCR, DPCR_INIT | 0x0000_0800).unwrap();
+        m.dma.store(IO_DMA_GPU_MADR, 0x1000).unwrap();
+        m.dma.store(IO_DMA_GPU_CHCR, 0x0100_0401).unwrap();
+        Dma::run(&mut m);
+        m.clock = u64::MAX;
+        Dma::complete_due(&mut m);
+        assert_eq!(m.dma.load(IO_DMA_GPU_CHCR).unwrap() & CHCR_BUSY, 0);
+        assert_eq!(m.dma.load(IO_DMA_GPU_MADR).unwrap(), 0x1000);
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+// SCN2681 dual UART on the development boards, used for the kernel TTY
+pub const DUART_MR: u32 = 0x0;
+pub const DUART_SR_CSR: u32 = 0x1;
+pub const DUART_CR: u32 = 0x2;
+pub const DUART_RHR_THR: u32 = 0x3;
+pub const DUART_IPCR_ACR: u32 = 0x4;
+pub const DUART_ISR_IMR: u32 = 0x5;
+pub const DUART_CTU: u32 = 0x6;
+pub const DUART_CTL: u32 = 0x7;
+// channel B registers follow channel A's at this offset
+pub const DUART_CHANNEL_B: u32 = 0x8;
+pub const DUART_IVR: u32 = 0xC;
+pub const DUART_IP_OPCR: u32 = 0xD;
+// reads of the output port registers are the start and stop counter commands
+pub const DUART_OP_SET: u32 = 0xE;
+pub const DUART_OP_RESET: u32 = 0xF;
+
+const DUART_SR_RXRDY: u32 = 0x01;
+const DUART_SR_FFULL: u32 = 0x02;
+const DUART_SR_TXRDY: u32 = 0x04;
+const DUART_SR_TXEMT: u32 = 0x08;
+
+const DUART_RX_FIFO_DEPTH: usize = 3;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct DuartChannel {
+    mr: [u8; 2],
+    // MR1 is selected after a pointer reset, MR2 after any MR access
+    mr_ptr: usize,
+    csr: u8,
+    rx_enabled: bool,
+    tx_enabled: bool,
+    // host input not yet in the receiver
+    rx_pending: VecDeque<u8>,
+    rx_fifo: VecDeque<u8>,
+    // transmitted bytes not yet taken by the host
+    tx: Vec<u8>,
+}
+
+impl DuartChannel {
+    fn new() -> DuartChannel {
+        DuartChannel {
+            mr: [0; 2],
+            mr_ptr: 0,
+            csr: 0,
+            rx_enabled: false,
+            tx_enabled: false,
+            rx_pending: VecDeque::new(),
+            rx_fifo: VecDeque::new(),
+            tx: vec![],
+        }
+    }
+
+    fn status(&self) -> u32 {
+        let mut sr = 0;
+        if !self.rx_fifo.is_empty() {
+            sr |= DUART_SR_RXRDY;
+        }
+        if self.rx_fifo.len() == DUART_RX_FIFO_DEPTH {
+            sr |= DUART_SR_FFULL;
+        }
+        // transmission is instant
+        if self.tx_enabled {
+            sr |= DUART_SR_TXRDY | DUART_SR_TXEMT;
+        }
+        sr
+    }
+
+    fn fill_rx(&mut self) {
+        while self.rx_enabled && self.rx_fifo.len() < DUART_RX_FIFO_DEPTH {
+            match self.rx_pending.pop_front() {
+                Some(byte) => self.rx_fifo.push_back(byte),
+                None => break,
+            }
+        }
+    }
+
+    fn command(&mut self, val: u8) {
+        match val & 0x03 {
+            1 => self.rx_enabled = true,
+            2 => self.rx_enabled = false,
+            _ => {}
+        }
+        match (val >> 2) & 0x03 {
+            1 => self.tx_enabled = true,
+            2 => self.tx_enabled = false,
+            _ => {}
+        }
+        match (val >> 4) & 0x07 {
+            1 => self.mr_ptr = 0,
+            2 => {
+                self.rx_enabled = false;
+                self.rx_fifo.clear();
+            }
+            3 => self.tx_enabled = false,
+            _ => {}
+        }
+        self.fill_rx();
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Duart {
+    channels: [DuartChannel; 2],
+    acr: u8,
+    imr: u8,
+    ivr: u8,
+    opcr: u8,
+    op: u8,
+    // counter/timer preload, the counter itself is not emulated and reads back the preload
+    ctr: u16,
+}
+
+impl Duart {
+    pub fn new() -> Duart {
+        Duart {
+            channels: [DuartChannel::new(), DuartChannel::new()],
+            acr: 0,
+            imr: 0,
+            ivr: 0x0F,
+            opcr: 0,
+            op: 0,
+            ctr: 0,
+        }
+    }
+
+    fn split(addr: u32) -> (usize, u32) {
+        match addr {
+            DUART_IVR..=DUART_OP_RESET => (0, addr),
+            _ => ((addr / DUART_CHANNEL_B) as usize, addr % DUART_CHANNEL_B),
+        }
+    }
+
+    // `addr` is the register index, 0x0-0xF
+    pub fn load(&self, addr: u32) -> u32 {
+        let (ch, reg) = Duart::split(addr);
+        let channel = &self.channels[ch];
+        match reg {
+            DUART_MR => channel.mr[channel.mr_ptr] as u32,
+            DUART_SR_CSR => channel.status(),
+            DUART_RHR_THR => channel.rx_fifo.front().copied().unwrap_or(0) as u32,
+            DUART_ISR_IMR => {
+                let [a, b] = &self.channels;
+                let isr = (a.status() & DUART_SR_TXRDY) >> 2
+                    | (a.status() & DUART_SR_RXRDY) << 1
+                    | (b.status() & DUART_SR_TXRDY) << 2
+                    | (b.status() & DUART_SR_RXRDY) << 5;
+                isr & self.imr as u32
+            }
+            DUART_CTU => (self.ctr >> 8) as u32,
+            DUART_CTL => (self.ctr & 0xFF) as u32,
+            DUART_IVR => self.ivr as u32,
+            DUART_OP_SET | DUART_OP_RESET => 0,
+            _ => {
+                println!("WARN: Unhandled DUART read at 0x{:X}", addr);
+                0
+            }
+        }
+    }
+
+    // reading the receive holding register pops it
+    pub fn commit_load(&mut self, addr: u32) {
+        let (ch, reg) = Duart::split(addr);
+        if reg == DUART_RHR_THR {
+            let channel = &mut self.channels[ch];
+            channel.rx_fifo.pop_front();
+            channel.fill_rx();
+        }
+    }
+
+    pub fn store(&mut self, addr: u32, val: u8) {
+        let (ch, reg) = Duart::split(addr);
+        let channel = &mut self.channels[ch];
+        match reg {
+            DUART_MR => {
+                channel.mr[channel.mr_ptr] = val;
+                channel.mr_ptr = 1;
+            }
+            DUART_SR_CSR => channel.csr = val,
+            DUART_CR => channel.command(val),
+            DUART_RHR_THR => {
+                if channel.tx_enabled {
+                    channel.tx.push(val);
+                } else {
+                    println!("WARN: DUART write with the transmitter disabled");
+                }
+            }
+            DUART_IPCR_ACR => self.acr = val,
+            DUART_ISR_IMR => self.imr = val,
+            DUART_CTU => self.ctr = (self.ctr & 0x00FF) | (val as u16) << 8,
+            DUART_CTL => self.ctr = (self.ctr & 0xFF00) | val as u16,
+            DUART_IVR => self.ivr = val,
+            DUART_IP_OPCR => self.opcr = val,
+            DUART_OP_SET => self.op |= val,
+            DUART_OP_RESET => self.op &= !val,
+            _ => println!("WARN: Unhandled DUART write {:02X} at 0x{:X}", val, addr),
+        }
+    }
+
+    pub fn push_input(&mut self, ch: usize, bytes: &[u8]) {
+        let channel = &mut self.channels[ch];
+        channel.rx_pending.extend(bytes);
+        channel.fill_rx();
+    }
+
+    pub fn take_output(&mut self, ch: usize) -> Vec<u8> {
+        std::mem::take(&mut self.channels[ch].tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duart_tty() {
+        let mut duart = Duart::new();
+        assert_eq!(duart.load(DUART_SR_CSR), 0);
+        // enable channel A receiver and transmitter
+        duart.store(DUART_CR, 0x05);
+        assert_eq!(duart.load(DUART_SR_CSR), DUART_SR_TXRDY | DUART_SR_TXEMT);
+        for &b in b"hi\n" {
+            duart.store(DUART_RHR_THR, b);
+        }
+        assert_eq!(duart.take_output(0), b"hi\n");
+        assert_eq!(duart.take_output(0), b"");
+
+        duart.push_input(0, b"abcd");
+        assert_eq!(
+            duart.load(DUART_SR_CSR) & 0x03,
+            DUART_SR_RXRDY | DUART_SR_FFULL
+        );
+        let mut read = vec![];
+        while duart.load(DUART_SR_CSR) & DUART_SR_RXRDY != 0 {
+            read.push(duart.load(DUART_RHR_THR) as u8);
+            duart.commit_load(DUART_RHR_THR);
+        }
+        assert_eq!(read, b"abcd");
+        // channel B is independent
+        assert_eq!(duart.load(DUART_CHANNEL_B + DUART_SR_CSR), 0);
+
+        duart.store(DUART_CTU, 0x12);
+        duart.store(DUART_CTL, 0x34);
+        assert_eq!((duart.load(DUART_CTU), duart.load(DUART_CTL)), (0x12, 0x34));
+        // start counter command, the output port is left alone
+        duart.store(DUART_OP_SET, 0x01);
+        assert_eq!(duart.load(DUART_OP_SET), 0);
+        assert_eq!(duart.op, 0x01);
+    }
+}
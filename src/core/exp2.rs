@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use super::duart::Duart;
+
+// offsets in expansion region 2
+pub const EXP2_DUART_BASE: u32 = 0x20;
+pub const EXP2_DUART_SIZE: u32 = 0x10;
+// 7-segment display on the development boards, written by the BIOS at each boot step
+pub const EXP2_POST: u32 = 0x41;
+pub const EXP2_POST2: u32 = 0x42;
+pub const EXP2_POST3: u32 = 0x70;
+
+const POST_HISTORY_MAX: usize = 256;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Exp2 {
+    pub duart: Duart,
+    post: u8,
+    post2: u8,
+    post3: u8,
+    // POST codes in the order written, consecutive repeats folded
+    post_history: Vec<u8>,
+}
+
+impl Exp2 {
+    pub fn new() -> Exp2 {
+        Exp2 {
+            duart: Duart::new(),
+            post: 0,
+            post2: 0,
+            post3: 0,
+            post_history: vec![],
+        }
+    }
+
+    pub fn post(&self) -> u8 {
+        self.post
+    }
+
+    pub fn post_history(&self) -> &[u8] {
+        &self.post_history
+    }
+
+    fn load_byte(&self, addr: u32) -> u8 {
+        match addr {
+            addr if (EXP2_DUART_BASE..EXP2_DUART_BASE + EXP2_DUART_SIZE).contains(&addr) => {
+                self.duart.load(addr - EXP2_DUART_BASE) as u8
+            }
+            EXP2_POST => self.post,
+            EXP2_POST2 => self.post2,
+            EXP2_POST3 => self.post3,
+            _ => {
+                println!("WARN: Unhandled EXP2 read at 0x{:08X}", addr);
+                0
+            }
+        }
+    }
+
+    fn store_byte(&mut self, addr: u32, val: u8) {
+        match addr {
+            addr if (EXP2_DUART_BASE..EXP2_DUART_BASE + EXP2_DUART_SIZE).contains(&addr) => {
+                self.duart.store(addr - EXP2_DUART_BASE, val)
+            }
+            EXP2_POST => {
+                println!("POST: {:02X}", val);
+                self.post = val;
+                if self.post_history.last() != Some(&val)
+                    && self.post_history.len() < POST_HISTORY_MAX
+                {
+                    self.post_history.push(val);
+                }
+            }
+            EXP2_POST2 => self.post2 = val,
+            EXP2_POST3 => {
+                println!("POST3: {:02X}", val);
+                self.post3 = val;
+            }
+            _ => println!("WARN: Unhandled EXP2 write {:02X} at 0x{:08X}", val, addr),
+        }
+    }
+
+    // the region is on an 8-bit bus, wider accesses are split into consecutive bytes
+    pub fn load(&self, addr: u32, bytes: u32) -> u32 {
+        (0..bytes).fold(0, |val, i| {
+            val | (self.load_byte(addr + i) as u32) << (8 * i)
+        })
+    }
+
+    pub fn commit_load(&mut self, addr: u32, bytes: u32) {
+        for addr in addr..addr + bytes {
+            if (EXP2_DUART_BASE..EXP2_DUART_BASE + EXP2_DUART_SIZE).contains(&addr) {
+                self.duart.commit_load(addr - EXP2_DUART_BASE);
+            }
+        }
+    }
+
+    pub fn store(&mut self, addr: u32, val: u32, bytes: u32) {
+        for i in 0..bytes {
+            self.store_byte(addr + i, (val >> (8 * i)) as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{bus::Bus, Machine, MachineMutation, MemOpSize};
+
+    #[test]
+    fn test_post_and_tty() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        for (addr, val) in [
+            (0x1F80_2041, 0x01),
+            (0x1F80_2041, 0x01),
+            (0xBF80_2041, 0x02),
+            // enable the channel A transmitter and send a byte
+            (0x1F80_2022, 0x04),
+            (0x1F80_2023, b'A' as u32),
+        ] {
+            let mut mu = MachineMutation::new();
+            mu.bus_write = Some((addr, val, MemOpSize::Byte));
+            Bus::mutate(&mut m, &mut mu).unwrap();
+        }
+        assert_eq!(m.post_code(), 0x02);
+        assert_eq!(m.exp2.post_history(), [0x01, 0x02]);
+        assert_eq!(m.take_tty_output(), b"A");
+
+        m.push_tty_input(b"x");
+        let mut mu = MachineMutation::new();
+        assert_eq!(Bus::lb(&m, &mut mu, 0x1F80_2023).unwrap(), 0);
+        m.exp2.duart.store(0x2, 0x01);
+        assert_eq!(Bus::lb(&m, &mut mu, 0x1F80_2023).unwrap(), b'x' as u32);
+        m.commit_exp2_load(&mu);
+        assert_eq!(Bus::lb(&m, &mut mu, 0x1F80_2021).unwrap() & 0x01, 0);
+    }
+}
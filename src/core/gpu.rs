@@ -0,0 +1,643 @@
+pub mod gpu_commands;
+pub mod gpu_raster;
+pub mod gpu_vram;
+
+use gpu_commands::*;
+use gpu_raster::{draw_line, draw_polygon, draw_rectangle, DrawEnv};
+use gpu_vram::Vram;
+use serde::{Deserialize, Serialize};
+
+use super::ioport::*;
+
+// video clocks per scanline and scanlines per frame
+const NTSC_LINE_CLOCKS: u64 = 3413;
+const NTSC_LINES: u64 = 263;
+const PAL_LINE_CLOCKS: u64 = 3406;
+const PAL_LINES: u64 = 314;
+
+// GPUSTAT
+const GPUSTAT_FIELD: u32 = 0x0000_2000;
+const GPUSTAT_DISPLAY_DISABLED: u32 = 0x0080_0000;
+const GPUSTAT_IRQ: u32 = 0x0100_0000;
+const GPUSTAT_DMA_REQUEST: u32 = 0x0200_0000;
+const GPUSTAT_READY_CMD: u32 = 0x0400_0000;
+const GPUSTAT_READY_VRAM_READ: u32 = 0x0800_0000;
+const GPUSTAT_READY_DMA: u32 = 0x1000_0000;
+const GPUSTAT_ODD_LINE: u32 = 0x8000_0000;
+
+// GP1(08h)
+const DISPLAY_MODE_PAL: u8 = 0x08;
+const DISPLAY_MODE_INTERLACE: u8 = 0x20;
+
+const GPU_VERSION: u32 = 2;
+
+// a rectangle of VRAM moved to or from the CPU, two pixels per word
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct VramTransfer {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    // pixels done so far
+    pos: u32,
+}
+
+impl VramTransfer {
+    fn new(xy: u32, size: u32) -> VramTransfer {
+        VramTransfer {
+            x: xy & 0x3FF,
+            y: (xy >> 16) & 0x1FF,
+            w: ((size & 0xFFFF).wrapping_sub(1) & 0x3FF) + 1,
+            h: ((size >> 16).wrapping_sub(1) & 0x1FF) + 1,
+            pos: 0,
+        }
+    }
+
+    fn len(&self) -> u32 {
+        self.w * self.h
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.len()
+    }
+
+    // the next pixel's VRAM coordinates
+    fn next(&mut self) -> (u32, u32) {
+        let xy = (self.x + self.pos % self.w, self.y + self.pos / self.w);
+        self.pos += 1;
+        xy
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum Gp0Mode {
+    Command,
+    // image data words of a CPU to VRAM copy
+    CpuToVram(VramTransfer),
+    // vertices of a polyline until the terminator
+    Polyline,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Gpu {
+    pub vram: Vram,
+    mode: Gp0Mode,
+    // words of the command being received
+    command: Vec<u32>,
+    vram_read: Option<VramTransfer>,
+    // GPUREAD outside of a VRAM to CPU copy
+    read_latch: u32,
+
+    // GP0(E1h), bits 0-8 and 11 are shared with GPUSTAT
+    texpage: u16,
+    // GP0(E2h)
+    tex_window: u32,
+    // GP0(E3h), GP0(E4h), inclusive
+    draw_area_left: u32,
+    draw_area_top: u32,
+    draw_area_right: u32,
+    draw_area_bottom: u32,
+    // GP0(E5h)
+    draw_offset_x: i32,
+    draw_offset_y: i32,
+    // GP0(E6h)
+    set_mask: bool,
+    check_mask: bool,
+
+    irq: bool,
+    display_disabled: bool,
+    dma_direction: u32,
+    display_start: u32,
+    display_h_range: u32,
+    display_v_range: u32,
+    display_mode: u8,
+    tex_disable_allowed: bool,
+    // interlace field, flipped every vblank
+    odd_field: bool,
+    // system clock of the last vblank, lines are counted from there
+    frame_start: u64,
+}
+
+impl Gpu {
+    pub fn new() -> Gpu {
+        let mut gpu = Gpu {
+            vram: Vram::new(),
+            mode: Gp0Mode::Command,
+            command: vec![],
+            vram_read: None,
+            read_latch: 0,
+            texpage: 0,
+            tex_window: 0,
+            draw_area_left: 0,
+            draw_area_top: 0,
+            draw_area_right: 0,
+            draw_area_bottom: 0,
+            draw_offset_x: 0,
+            draw_offset_y: 0,
+            set_mask: false,
+            check_mask: false,
+            irq: false,
+            display_disabled: true,
+            dma_direction: 0,
+            display_start: 0,
+            display_h_range: 0,
+            display_v_range: 0,
+            display_mode: 0,
+            tex_disable_allowed: false,
+            odd_field: false,
+            frame_start: 0,
+        };
+        gpu.reset();
+        gpu
+    }
+
+    // GP1(00h), VRAM is left untouched
+    fn reset(&mut self) {
+        self.mode = Gp0Mode::Command;
+        self.command.clear();
+        self.vram_read = None;
+        self.texpage = 0;
+        self.tex_window = 0;
+        self.draw_area_left = 0;
+        self.draw_area_top = 0;
+        self.draw_area_right = 0;
+        self.draw_area_bottom = 0;
+        self.draw_offset_x = 0;
+        self.draw_offset_y = 0;
+        self.set_mask = false;
+        self.check_mask = false;
+        self.irq = false;
+        self.display_disabled = true;
+        self.dma_direction = 0;
+        self.display_start = 0;
+        self.display_h_range = 0x00C0_0200;
+        self.display_v_range = 0x0040_0010;
+        self.display_mode = 0;
+    }
+
+    pub fn load(&self, addr: u32, clock: u64) -> Result<u32, String> {
+        match addr {
+            IO_GPU_REG0 => Ok(self.read()),
+            IO_GPU_REG1 => Ok(self.status(clock)),
+            _ => Err(format!("Unhandled GPU read at 0x{:08X}", addr)),
+        }
+    }
+
+    pub fn store(&mut self, addr: u32, val: u32) -> Result<(), String> {
+        match addr {
+            IO_GPU_REG0 => self.gp0(val),
+            IO_GPU_REG1 => self.gp1(val),
+            _ => return Err(format!("Unhandled GPU write at 0x{:08X}", addr)),
+        }
+        Ok(())
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.irq
+    }
+
+    pub fn status(&self, clock: u64) -> u32 {
+        let mut stat = (self.texpage as u32 & 0x07FF) | ((self.texpage as u32 & 0x0800) << 4);
+        stat |= (self.set_mask as u32) << 11 | (self.check_mask as u32) << 12;
+        // the field bit is always set outside of interlaced modes
+        if self.display_mode & DISPLAY_MODE_INTERLACE == 0 || self.odd_field {
+            stat |= GPUSTAT_FIELD;
+        }
+        let mode = self.display_mode as u32;
+        stat |= ((mode & 0x40) >> 6) << 16 | (mode & 0x3F) << 17 | ((mode & 0x80) >> 7) << 14;
+        if self.display_disabled {
+            stat |= GPUSTAT_DISPLAY_DISABLED;
+        }
+        if self.irq {
+            stat |= GPUSTAT_IRQ;
+        }
+        // commands are executed as soon as they are complete
+        let ready_cmd = matches!(self.mode, Gp0Mode::Command);
+        if ready_cmd && self.command.is_empty() {
+            stat |= GPUSTAT_READY_CMD;
+        }
+        if self.vram_read.is_some() {
+            stat |= GPUSTAT_READY_VRAM_READ;
+        }
+        if ready_cmd || matches!(self.mode, Gp0Mode::CpuToVram(_)) {
+            stat |= GPUSTAT_READY_DMA;
+        }
+        stat |= match self.dma_direction {
+            0 => 0,
+            1 => GPUSTAT_DMA_REQUEST,
+            2 => (stat & GPUSTAT_READY_DMA) >> 3,
+            _ => (stat & GPUSTAT_READY_VRAM_READ) >> 2,
+        };
+        stat |= self.dma_direction << 29;
+        if self.odd_line(clock) {
+            stat |= GPUSTAT_ODD_LINE;
+        }
+        stat
+    }
+
+    // GPUREAD, must be followed by commit_read
+    pub fn read(&self) -> u32 {
+        match self.vram_read {
+            Some(mut transfer) => {
+                let mut val = 0;
+                for i in 0..2 {
+                    if !transfer.done() {
+                        let (x, y) = transfer.next();
+                        val |= (self.vram.get(x, y) as u32) << (16 * i);
+                    }
+                }
+                val
+            }
+            None => self.read_latch,
+        }
+    }
+
+    // advances a VRAM to CPU copy past the word read
+    pub fn commit_read(&mut self) {
+        if let Some(transfer) = &mut self.vram_read {
+            transfer.pos += 2;
+            if transfer.done() {
+                self.vram_read = None;
+            }
+        }
+    }
+
+    pub fn gp0(&mut self, val: u32) {
+        match &mut self.mode {
+            Gp0Mode::CpuToVram(transfer) => {
+                let mut transfer = *transfer;
+                for pixel in [val as u16, (val >> 16) as u16] {
+                    if !transfer.done() {
+                        let (x, y) = transfer.next();
+                        self.vram
+                            .set_masked(x, y, pixel, self.set_mask, self.check_mask);
+                    }
+                }
+                self.mode = if transfer.done() {
+                    Gp0Mode::Command
+                } else {
+                    Gp0Mode::CpuToVram(transfer)
+                };
+            }
+            Gp0Mode::Polyline => {
+                if val & POLYLINE_END_MASK == POLYLINE_END && self.polyline_group_start() {
+                    let line = decode_line(&self.command);
+                    self.command.clear();
+                    self.mode = Gp0Mode::Command;
+                    self.draw(Primitive::Line(line));
+                } else {
+                    self.command.push(val);
+                }
+            }
+            Gp0Mode::Command => {
+                self.command.push(val);
+                if self.command.len() < gp0_command_words(self.command[0]) {
+                    return;
+                }
+                let op = self.command[0] >> 24;
+                if (0x40..=0x5F).contains(&op) && op & GP0_LINE_POLY != 0 {
+                    self.mode = Gp0Mode::Polyline;
+                    return;
+                }
+                let command = std::mem::take(&mut self.command);
+                self.execute(&command);
+            }
+        }
+    }
+
+    // whether the next polyline word starts a vertex, its color when shaded
+    fn polyline_group_start(&self) -> bool {
+        let shaded = (self.command[0] >> 24) & GP0_LINE_SHADED != 0;
+        !shaded || self.command.len().is_multiple_of(2)
+    }
+
+    fn execute(&mut self, command: &[u32]) {
+        let op = command[0] >> 24;
+        match op {
+            0x00 | 0x03..=0x1E => {}
+            // clear texture cache
+            0x01 => {}
+            0x02 => self.fill_rect(command),
+            0x1F => self.irq = true,
+            0x20..=0x3F => {
+                let polygon = decode_polygon(command);
+                if let Some(texpage) = polygon.texpage {
+                    self.set_texpage(texpage, 0x09FF);
+                }
+                self.draw(Primitive::Polygon(polygon));
+            }
+            0x40..=0x5F => self.draw(Primitive::Line(decode_line(command))),
+            0x60..=0x7F => self.draw(Primitive::Rectangle(decode_rectangle(command))),
+            0x80..=0x9F => self.copy_vram(command),
+            0xA0..=0xBF => {
+                self.mode = Gp0Mode::CpuToVram(VramTransfer::new(command[1], command[2]));
+            }
+            0xC0..=0xDF => self.vram_read = Some(VramTransfer::new(command[1], command[2])),
+            0xE1 => self.set_texpage(command[0] as u16, 0x3FFF),
+            0xE2 => self.tex_window = command[0] & 0x000F_FFFF,
+            0xE3 => {
+                self.draw_area_left = command[0] & 0x3FF;
+                self.draw_area_top = (command[0] >> 10) & 0x1FF;
+            }
+            0xE4 => {
+                self.draw_area_right = command[0] & 0x3FF;
+                self.draw_area_bottom = (command[0] >> 10) & 0x1FF;
+            }
+            0xE5 => {
+                self.draw_offset_x = ((command[0] as i32) << 21) >> 21;
+                self.draw_offset_y = ((command[0] as i32) << 10) >> 21;
+            }
+            0xE6 => {
+                self.set_mask = command[0] & 1 != 0;
+                self.check_mask = command[0] & 2 != 0;
+            }
+            _ => println!("WARN: Unknown GP0 command {:08X}", command[0]),
+        }
+    }
+
+    // texture disable only sticks while allowed by GP1(09h)
+    // polygons only carry the page, depth, blending and texture disable bits
+    fn set_texpage(&mut self, val: u16, mask: u16) {
+        let val = if self.tex_disable_allowed {
+            val
+        } else {
+            val & !0x0800
+        };
+        self.texpage = (self.texpage & !mask) | (val & mask);
+    }
+
+    fn draw_env(&self) -> DrawEnv {
+        DrawEnv {
+            texpage: self.texpage,
+            tex_window: self.tex_window,
+            area_left: self.draw_area_left as i32,
+            area_top: self.draw_area_top as i32,
+            area_right: self.draw_area_right as i32,
+            area_bottom: self.draw_area_bottom as i32,
+            offset_x: self.draw_offset_x,
+            offset_y: self.draw_offset_y,
+            set_mask: self.set_mask,
+            check_mask: self.check_mask,
+        }
+    }
+
+    fn draw(&mut self, primitive: Primitive) {
+        let env = self.draw_env();
+        match &primitive {
+            Primitive::Polygon(polygon) => draw_polygon(&mut self.vram, &env, polygon),
+            Primitive::Rectangle(rect) => draw_rectangle(&mut self.vram, &env, rect),
+            Primitive::Line(line) => draw_line(&mut self.vram, &env, line),
+        }
+    }
+
+    // filled in 16 pixel steps, ignoring the drawing area and the mask settings
+    fn fill_rect(&mut self, command: &[u32]) {
+        let c = command[0];
+        let pixel = ((c >> 3) & 0x1F) | ((c >> 11) & 0x1F) << 5 | ((c >> 19) & 0x1F) << 10;
+        let x = command[1] & 0x3F0;
+        let y = (command[1] >> 16) & 0x1FF;
+        let w = ((command[2] & 0x3FF) + 0xF) & !0xF;
+        let h = (command[2] >> 16) & 0x1FF;
+        for dy in 0..h {
+            for dx in 0..w {
+                self.vram.set(x + dx, y + dy, pixel as u16);
+            }
+        }
+    }
+
+    fn copy_vram(&mut self, command: &[u32]) {
+        let src = VramTransfer::new(command[1], command[3]);
+        let mut dst = VramTransfer::new(command[2], command[3]);
+        let mut src = src;
+        while !src.done() {
+            let (sx, sy) = src.next();
+            let (dx, dy) = dst.next();
+            let pixel = self.vram.get(sx, sy);
+            self.vram
+                .set_masked(dx, dy, pixel, self.set_mask, self.check_mask);
+        }
+    }
+
+    pub fn gp1(&mut self, val: u32) {
+        match val >> 24 {
+            0x00 => self.reset(),
+            0x01 => {
+                self.mode = Gp0Mode::Command;
+                self.command.clear();
+            }
+            0x02 => self.irq = false,
+            0x03 => self.display_disabled = val & 1 != 0,
+            0x04 => self.dma_direction = val & 3,
+            0x05 => self.display_start = val & 0x7_FFFF,
+            0x06 => self.display_h_range = val & 0xFF_FFFF,
+            0x07 => self.display_v_range = val & 0xF_FFFF,
+            0x08 => self.display_mode = val as u8,
+            0x09 => self.tex_disable_allowed = val & 1 != 0,
+            0x10..=0x1F => self.get_info(val),
+            _ => println!("WARN: Unknown GP1 command {:08X}", val),
+        }
+    }
+
+    // GP1(10h), latches the requested state into GPUREAD, decoded as on the newer GPU
+    fn get_info(&mut self, val: u32) {
+        self.read_latch = match val & 0xF {
+            2 => self.tex_window,
+            3 => self.draw_area_left | self.draw_area_top << 10,
+            4 => self.draw_area_right | self.draw_area_bottom << 10,
+            5 => (self.draw_offset_x as u32 & 0x7FF) | (self.draw_offset_y as u32 & 0x7FF) << 11,
+            7 => GPU_VERSION,
+            8 => 0,
+            _ => self.read_latch,
+        };
+    }
+
+    fn video_timings(&self) -> (u64, u64) {
+        if self.display_mode & DISPLAY_MODE_PAL != 0 {
+            (PAL_LINES, PAL_LINE_CLOCKS)
+        } else {
+            (NTSC_LINES, NTSC_LINE_CLOCKS)
+        }
+    }
+
+    // system clocks per frame, the video clock runs at 11/7 of the system clock
+    pub fn frame_clocks(&self) -> u64 {
+        let (lines, line_clocks) = self.video_timings();
+        lines * line_clocks * 7 / 11
+    }
+
+    // GPUSTAT bit 31, the field in interlaced modes and the scanline parity otherwise,
+    // lines are counted from the vblank rather than the top of the display area
+    fn odd_line(&self, clock: u64) -> bool {
+        if self.display_mode & DISPLAY_MODE_INTERLACE != 0 {
+            return self.odd_field;
+        }
+        let (_, line_clocks) = self.video_timings();
+        let line = clock.saturating_sub(self.frame_start) * 11 / (7 * line_clocks);
+        line & 1 != 0
+    }
+
+    pub fn vblank(&mut self, clock: u64) {
+        self.odd_field = !self.odd_field;
+        self.frame_start = clock;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_vram_copies() {
+        let mut gpu = Gpu::new();
+        // 3x2 pixels at (1020, 511), wrapping around both edges
+        for val in [
+            0xA000_0000,
+            0x01FF_03FC,
+            0x0002_0003,
+            0x0002_0001,
+            0x0004_0003,
+            0x0006_0005,
+        ] {
+            gpu.gp0(val);
+        }
+        assert_eq!(gpu.vram.get(1020, 511), 1);
+        assert_eq!(gpu.vram.get(1022, 511), 3);
+        assert_eq!(gpu.vram.get(1020, 0), 4);
+        assert_eq!(gpu.vram.get(1022, 0), 6);
+        assert_ne!(gpu.status(0) & GPUSTAT_READY_CMD, 0);
+
+        // VRAM to VRAM, then back to the CPU
+        for val in [0x8000_0000, 0x01FF_03FC, 0x0010_0010, 0x0002_0003] {
+            gpu.gp0(val);
+        }
+        for val in [0xC000_0000, 0x0010_0010, 0x0002_0003] {
+            gpu.gp0(val);
+        }
+        assert_ne!(gpu.status(0) & GPUSTAT_READY_VRAM_READ, 0);
+        let mut words = vec![];
+        while gpu.status(0) & GPUSTAT_READY_VRAM_READ != 0 {
+            words.push(gpu.read());
+            gpu.commit_read();
+        }
+        assert_eq!(words, [0x0002_0001, 0x0004_0003, 0x0006_0005]);
+    }
+
+    #[test]
+    fn test_mask_bits() {
+        let mut gpu = Gpu::new();
+        gpu.gp0(0xE600_0003);
+        for val in [0xA000_0000, 0x0000_0000, 0x0001_0002, 0x0000_8001] {
+            gpu.gp0(val);
+        }
+        // set mask forces bit 15, check mask protects the pixel on the second write
+        assert_eq!(gpu.vram.get(0, 0), 0x8001);
+        assert_eq!(gpu.vram.get(1, 0), 0x8000);
+        for val in [0xA000_0000, 0x0000_0000, 0x0001_0002, 0x1234_4321] {
+            gpu.gp0(val);
+        }
+        assert_eq!(gpu.vram.get(0, 0), 0x8001);
+    }
+
+    #[test]
+    fn test_state_commands() {
+        let mut gpu = Gpu::new();
+        gpu.gp0(0xE100_0A15);
+        gpu.gp0(0xE300_2C08);
+        gpu.gp0(0xE407_FFFF);
+        gpu.gp0(0xE500_0FFF);
+        gpu.gp1(0x0900_0001);
+        gpu.gp0(0xE100_0A15);
+        // texpage bits, dither and texture disable
+        assert_eq!(gpu.status(0) & 0x0000_87FF, 0x0000_8215);
+
+        gpu.gp1(0x1000_0003);
+        assert_eq!(gpu.read(), 0x0000_2C08);
+        gpu.gp1(0x1000_0004);
+        assert_eq!(gpu.read(), 0x0007_FFFF);
+        gpu.gp1(0x1000_0005);
+        assert_eq!(gpu.read(), 0x0000_0FFF);
+        assert_eq!((gpu.draw_offset_x, gpu.draw_offset_y), (-1, 1));
+        gpu.gp1(0x1000_0007);
+        assert_eq!(gpu.read(), GPU_VERSION);
+        // indices 8-15 aren't mirrors of 0-7
+        gpu.gp1(0x1000_0008);
+        assert_eq!(gpu.read(), 0);
+        gpu.gp1(0x1000_000F);
+        assert_eq!(gpu.read(), 0);
+
+        gpu.gp0(0x1F00_0000);
+        assert!(gpu.irq_line());
+        gpu.gp1(0x0200_0000);
+        assert!(!gpu.irq_line());
+    }
+
+    #[test]
+    fn test_polygon_texpage() {
+        let mut gpu = Gpu::new();
+        gpu.gp0(0xE100_0215);
+        // textured triangle on page 0
+        for val in [
+            0x2400_0000,
+            0x0000_0000,
+            0x0000_0000,
+            0x0000_0010,
+            0x0000_0000,
+            0x0010_0000,
+            0x0000_0000,
+        ] {
+            gpu.gp0(val);
+        }
+        // dithering set by GP0(E1h) stays on
+        assert_eq!(gpu.status(0) & 0x0000_87FF, 0x0000_0200);
+    }
+
+    #[test]
+    fn test_fill_and_polyline() {
+        let mut gpu = Gpu::new();
+        gpu.gp0(0x0200_F8F8);
+        gpu.gp0(0x0001_0005);
+        gpu.gp0(0x0001_0011);
+        // rounded to 16 pixels from x = 0
+        assert_eq!(gpu.vram.get(31, 1), 0x03FF);
+        assert_eq!(gpu.vram.get(32, 1), 0);
+        assert_eq!(gpu.vram.get(0, 2), 0);
+
+        for val in [0x4800_0000, 0x0000_0000, 0x0010_0010, 0x0020_0000] {
+            gpu.gp0(val);
+        }
+        assert!(matches!(gpu.mode, Gp0Mode::Polyline));
+        gpu.gp0(0x5555_5555);
+        assert!(matches!(gpu.mode, Gp0Mode::Command));
+    }
+
+    #[test]
+    fn test_draw_state() {
+        let mut gpu = Gpu::new();
+        // area (2, 2)-(5, 5), offset (1, 1), then an 8x8 flat sprite at the origin
+        gpu.gp0(0xE300_0802);
+        gpu.gp0(0xE400_1405);
+        gpu.gp0(0xE500_0801);
+        gpu.gp0(0x7000_00F8);
+        gpu.gp0(0x0000_0000);
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = (2..=5).contains(&x) && (2..=5).contains(&y);
+                assert_eq!(gpu.vram.get(x, y), if inside { 0x001F } else { 0 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_odd_line() {
+        let mut gpu = Gpu::new();
+        assert_eq!(gpu.display_h_range, 0x00C0_0200);
+        // NTSC lines are 3413 video clocks, 2171.9 system clocks
+        gpu.vblank(1000);
+        assert_eq!(gpu.status(1000) & GPUSTAT_ODD_LINE, 0);
+        assert_ne!(gpu.status(1000 + 2172) & GPUSTAT_ODD_LINE, 0);
+        assert_eq!(gpu.status(1000 + 2 * 2172) & GPUSTAT_ODD_LINE, 0);
+        // the field in interlaced modes
+        gpu.gp1(0x0800_0020);
+        assert_ne!(gpu.status(1000) & GPUSTAT_ODD_LINE, 0);
+    }
+}
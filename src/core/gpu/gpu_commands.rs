@@ -0,0 +1,264 @@
+// GP0 drawing commands, decoded from the command words
+pub const GP0_POLYGON_SHADED: u32 = 0x10;
+pub const GP0_POLYGON_QUAD: u32 = 0x08;
+pub const GP0_TEXTURED: u32 = 0x04;
+pub const GP0_SEMI_TRANSPARENT: u32 = 0x02;
+pub const GP0_RAW_TEXTURE: u32 = 0x01;
+pub const GP0_LINE_SHADED: u32 = 0x10;
+pub const GP0_LINE_POLY: u32 = 0x08;
+pub const GP0_RECT_SIZE_SHIFT: u32 = 3;
+
+// ends a polyline, in place of the first word of a vertex
+pub const POLYLINE_END_MASK: u32 = 0xF000_F000;
+pub const POLYLINE_END: u32 = 0x5000_5000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    pub x: i32,
+    pub y: i32,
+    // 24-bit BGR
+    pub color: u32,
+    pub u: u8,
+    pub v: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct Polygon {
+    pub vertices: Vec<Vertex>,
+    pub shaded: bool,
+    pub textured: bool,
+    pub semi_transparent: bool,
+    // texture colors are used without modulation
+    pub raw_texture: bool,
+    pub clut: u16,
+    // GP0(E1h) bits 0-8 and 11, from the second vertex of textured polygons
+    pub texpage: Option<u16>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rectangle {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+    pub color: u32,
+    pub u: u8,
+    pub v: u8,
+    pub clut: u16,
+    pub textured: bool,
+    pub semi_transparent: bool,
+    pub raw_texture: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Line {
+    // two for a single line, more for a polyline
+    pub vertices: Vec<Vertex>,
+    pub shaded: bool,
+    pub semi_transparent: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum Primitive {
+    Polygon(Polygon),
+    Rectangle(Rectangle),
+    Line(Line),
+}
+
+// 11-bit signed vertex coordinates
+fn vertex_xy(word: u32) -> (i32, i32) {
+    let x = ((word as i32) << 21) >> 21;
+    let y = ((word as i32) << 5) >> 21;
+    (x, y)
+}
+
+fn vertex(word: u32, color: u32) -> Vertex {
+    let (x, y) = vertex_xy(word);
+    Vertex {
+        x,
+        y,
+        color: color & 0x00FF_FFFF,
+        u: 0,
+        v: 0,
+    }
+}
+
+// words in a command including the first, polylines return their minimal length
+pub fn gp0_command_words(cmd: u32) -> usize {
+    let op = cmd >> 24;
+    match op {
+        0x02 => 3,
+        0x20..=0x3F => {
+            let vertices = if op & GP0_POLYGON_QUAD != 0 { 4 } else { 3 };
+            let per_vertex =
+                1 + (op & GP0_TEXTURED != 0) as usize + (op & GP0_POLYGON_SHADED != 0) as usize;
+            // the first color is in the command word
+            1 + vertices * per_vertex - (op & GP0_POLYGON_SHADED != 0) as usize
+        }
+        0x40..=0x5F => {
+            if op & GP0_LINE_SHADED != 0 {
+                4
+            } else {
+                3
+            }
+        }
+        0x60..=0x7F => {
+            let variable = (op >> GP0_RECT_SIZE_SHIFT) & 3 == 0;
+            2 + (op & GP0_TEXTURED != 0) as usize + variable as usize
+        }
+        0x80..=0x9F => 4,
+        0xA0..=0xDF => 3,
+        _ => 1,
+    }
+}
+
+pub fn decode_polygon(words: &[u32]) -> Polygon {
+    let op = words[0] >> 24;
+    let count = if op & GP0_POLYGON_QUAD != 0 { 4 } else { 3 };
+    let shaded = op & GP0_POLYGON_SHADED != 0;
+    let textured = op & GP0_TEXTURED != 0;
+
+    let mut vertices = Vec::with_capacity(count);
+    let mut clut = 0;
+    let mut texpage = None;
+    let mut idx = 0;
+    for i in 0..count {
+        let color = if i == 0 || !shaded {
+            words[0]
+        } else {
+            idx += 1;
+            words[idx]
+        };
+        idx += 1;
+        let mut v = vertex(words[idx], color);
+        if textured {
+            idx += 1;
+            let uv = words[idx];
+            v.u = uv as u8;
+            v.v = (uv >> 8) as u8;
+            match i {
+                0 => clut = (uv >> 16) as u16,
+                1 => texpage = Some((uv >> 16) as u16 & 0x09FF),
+                _ => {}
+            }
+        }
+        vertices.push(v);
+    }
+    Polygon {
+        vertices,
+        shaded,
+        textured,
+        semi_transparent: op & GP0_SEMI_TRANSPARENT != 0,
+        raw_texture: op & GP0_RAW_TEXTURE != 0,
+        clut,
+        texpage,
+    }
+}
+
+pub fn decode_rectangle(words: &[u32]) -> Rectangle {
+    let op = words[0] >> 24;
+    let textured = op & GP0_TEXTURED != 0;
+    let (x, y) = vertex_xy(words[1]);
+    let (mut u, mut v, mut clut) = (0, 0, 0);
+    let mut idx = 2;
+    if textured {
+        u = words[idx] as u8;
+        v = (words[idx] >> 8) as u8;
+        clut = (words[idx] >> 16) as u16;
+        idx += 1;
+    }
+    let (w, h) = match (op >> GP0_RECT_SIZE_SHIFT) & 3 {
+        0 => (words[idx] & 0x3FF, (words[idx] >> 16) & 0x1FF),
+        1 => (1, 1),
+        2 => (8, 8),
+        _ => (16, 16),
+    };
+    Rectangle {
+        x,
+        y,
+        w,
+        h,
+        color: words[0] & 0x00FF_FFFF,
+        u,
+        v,
+        clut,
+        textured,
+        semi_transparent: op & GP0_SEMI_TRANSPARENT != 0,
+        raw_texture: op & GP0_RAW_TEXTURE != 0,
+    }
+}
+
+// `words` holds a single line, or a polyline without its terminator
+pub fn decode_line(words: &[u32]) -> Line {
+    let op = words[0] >> 24;
+    let shaded = op & GP0_LINE_SHADED != 0;
+    let mut vertices = vec![vertex(words[1], words[0])];
+    let mut idx = 2;
+    while idx < words.len() {
+        let color = if shaded {
+            idx += 1;
+            words[idx - 1]
+        } else {
+            words[0]
+        };
+        vertices.push(vertex(words[idx], color));
+        idx += 1;
+    }
+    Line {
+        vertices,
+        shaded,
+        semi_transparent: op & GP0_SEMI_TRANSPARENT != 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_words() {
+        // flat triangle, shaded textured quad, polyline, variable textured rectangle
+        assert_eq!(gp0_command_words(0x2000_0000), 4);
+        assert_eq!(gp0_command_words(0x3C00_0000), 12);
+        assert_eq!(gp0_command_words(0x4800_0000), 3);
+        assert_eq!(gp0_command_words(0x6400_0000), 4);
+        assert_eq!(gp0_command_words(0x7C00_0000), 3);
+    }
+
+    #[test]
+    fn test_decode_shaded_textured_triangle() {
+        let poly = decode_polygon(&[
+            0x3400_00FF,
+            0x0010_FFF0,
+            0x7FC0_0201,
+            0x0000_FF00,
+            0x0020_0030,
+            0x0015_0403,
+            0x00FF_0000,
+            0x0030_0010,
+            0x0000_0605,
+        ]);
+        assert!(poly.shaded && poly.textured);
+        assert_eq!(poly.clut, 0x7FC0);
+        assert_eq!(poly.texpage, Some(0x0015));
+        assert_eq!(
+            poly.vertices[0],
+            Vertex {
+                x: -16,
+                y: 16,
+                color: 0xFF,
+                u: 1,
+                v: 2
+            }
+        );
+        assert_eq!(poly.vertices[2].color, 0xFF_0000);
+        assert_eq!((poly.vertices[2].x, poly.vertices[2].y), (16, 48));
+    }
+
+    #[test]
+    fn test_decode_shaded_polyline() {
+        let line = decode_line(&[0x5800_0001, 0x0000_0000, 0x0000_0002, 0x0010_0010]);
+        assert_eq!(line.vertices.len(), 2);
+        assert_eq!(line.vertices[1].color, 2);
+    }
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+pub const VRAM_WIDTH: u32 = 1024;
+pub const VRAM_HEIGHT: u32 = 512;
+
+// bit 15 of a pixel, checked and set by the mask settings
+pub const VRAM_MASK_BIT: u16 = 0x8000;
+
+// 1MB frame buffer of 1024x512 15-bit pixels, addressed with wrap around
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Vram {
+    pixels: Vec<u16>,
+}
+
+impl Vram {
+    pub fn new() -> Vram {
+        Vram {
+            pixels: vec![0; (VRAM_WIDTH * VRAM_HEIGHT) as usize],
+        }
+    }
+
+    fn index(x: u32, y: u32) -> usize {
+        ((y % VRAM_HEIGHT) * VRAM_WIDTH + (x % VRAM_WIDTH)) as usize
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> u16 {
+        self.pixels[Vram::index(x, y)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, pixel: u16) {
+        self.pixels[Vram::index(x, y)] = pixel;
+    }
+
+    // skips pixels with the mask bit when checking, and forces it when setting
+    pub fn set_masked(&mut self, x: u32, y: u32, pixel: u16, set_mask: bool, check_mask: bool) {
+        let idx = Vram::index(x, y);
+        if check_mask && self.pixels[idx] & VRAM_MASK_BIT != 0 {
+            return;
+        }
+        self.pixels[idx] = if set_mask {
+            pixel | VRAM_MASK_BIT
+        } else {
+            pixel
+        };
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+}
@@ -0,0 +1,185 @@
+use super::{cache_ctrl::CacheControl, MachineMutation};
+
+use serde::{Deserialize, Serialize};
+
+// 4KB direct-mapped, 256 lines of 4 words
+const ICACHE_LINES: usize = 0x100;
+const ICACHE_TAG_MASK: u32 = 0x1FFF_F000;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct ICacheLine {
+    tag: u32,
+    // one bit per word
+    valid: u8,
+    data: [u32; 4],
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ICache {
+    lines: Vec<ICacheLine>,
+    enabled: bool,
+    tag_test: bool,
+    invalidate_mode: bool,
+    lock_mode: bool,
+}
+
+fn line_index(addr: u32) -> usize {
+    ((addr >> 4) as usize) & (ICACHE_LINES - 1)
+}
+
+fn word_index(addr: u32) -> usize {
+    ((addr >> 2) & 3) as usize
+}
+
+impl ICache {
+    pub fn new() -> ICache {
+        ICache {
+            lines: vec![
+                ICacheLine {
+                    tag: 0,
+                    valid: 0,
+                    data: [0; 4],
+                };
+                ICACHE_LINES
+            ],
+            enabled: false,
+            tag_test: false,
+            invalidate_mode: false,
+            lock_mode: false,
+        }
+    }
+
+    // called on writes to the cache control register (0xFFFE0130)
+    pub fn configure(&mut self, cache_ctrl: &CacheControl) {
+        self.enabled = cache_ctrl.icache_enabled();
+        self.tag_test = cache_ctrl.tag_test();
+        self.invalidate_mode = cache_ctrl.invalidate_mode();
+        self.lock_mode = cache_ctrl.lock_mode();
+    }
+
+    // instruction fetches from KUSEG and KSEG0 go through the cache
+    pub fn is_cached(&self, addr: u32) -> bool {
+        self.enabled && addr < 0xA000_0000
+    }
+
+    pub fn fetch(&self, addr: u32) -> Option<u32> {
+        let line = &self.lines[line_index(addr)];
+        let word = word_index(addr);
+        if line.tag == addr & ICACHE_TAG_MASK && line.valid & (1 << word) != 0 {
+            Some(line.data[word])
+        } else {
+            None
+        }
+    }
+
+    // a miss refills the line from the missed word up to the end of the line
+    pub fn fill(&mut self, addr: u32, data: &[u32; 4]) {
+        let line = &mut self.lines[line_index(addr)];
+        let word = word_index(addr);
+        if line.tag != addr & ICACHE_TAG_MASK {
+            line.tag = addr & ICACHE_TAG_MASK;
+            line.valid = 0;
+        }
+        line.data[word..].copy_from_slice(&data[word..]);
+        line.valid |= (0xF << word) & 0xF;
+    }
+
+    pub fn mutate(&mut self, mu: &MachineMutation) {
+        if let Some((addr, line)) = &mu.icache_fill {
+            self.fill(*addr, line);
+        }
+    }
+
+    // stores while Status.IsC is set
+    pub fn isolated_store(&mut self, addr: u32, val: u32) {
+        let line = &mut self.lines[line_index(addr)];
+        let word = word_index(addr);
+        if self.tag_test {
+            // tag test mode: the line is retagged and all its words are invalidated
+            line.tag = addr & ICACHE_TAG_MASK;
+            line.valid = 0;
+        } else if self.invalidate_mode {
+            line.valid = 0;
+        } else if self.lock_mode {
+            if line.tag != addr & ICACHE_TAG_MASK {
+                line.tag = addr & ICACHE_TAG_MASK;
+                line.valid = 0;
+            }
+            line.data[word] = val;
+            line.valid |= 1 << word;
+        } else {
+            line.data[word] = val;
+        }
+    }
+
+    pub fn isolated_load(&self, addr: u32) -> u32 {
+        let line = &self.lines[line_index(addr)];
+        if self.tag_test {
+            line.tag | line.valid as u32
+        } else {
+            line.data[word_index(addr)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::cache_ctrl::*;
+
+    fn ctrl(val: u32) -> CacheControl {
+        let mut cache_ctrl = CacheControl::new();
+        cache_ctrl.store(val);
+        cache_ctrl
+    }
+
+    #[test]
+    fn test_fill_from_missed_word() {
+        let mut icache = ICache::new();
+        icache.configure(&ctrl(CACHE_CTRL_IS1));
+        assert!(icache.is_cached(0x8000_0108));
+        assert!(!icache.is_cached(0xA000_0108));
+        assert_eq!(icache.fetch(0x8000_0108), None);
+        icache.fill(0x8000_0108, &[0, 0, 0x2222, 0x3333]);
+        assert_eq!(icache.fetch(0x8000_0108), Some(0x2222));
+        assert_eq!(icache.fetch(0x8000_010C), Some(0x3333));
+        // words before the missed one are not loaded
+        assert_eq!(icache.fetch(0x8000_0100), None);
+        // same index, different tag
+        assert_eq!(icache.fetch(0x8000_1108), None);
+    }
+
+    #[test]
+    fn test_tag_test_invalidate() {
+        let mut icache = ICache::new();
+        icache.configure(&ctrl(CACHE_CTRL_IS1));
+        icache.fill(0x8000_0100, &[0x1111, 0x2222, 0x3333, 0x4444]);
+        // BIOS icache clear: tag test mode, store 0 to every line
+        icache.configure(&ctrl(CACHE_CTRL_IS1 | CACHE_CTRL_TAG));
+        for i in 0..0x100 {
+            icache.isolated_store(i * 16, 0);
+        }
+        icache.configure(&ctrl(CACHE_CTRL_IS1));
+        assert_eq!(icache.fetch(0x8000_0100), None);
+    }
+
+    #[test]
+    fn test_isolated_data_store() {
+        let mut icache = ICache::new();
+        icache.configure(&ctrl(CACHE_CTRL_IS1));
+        icache.fill(0x8000_0100, &[0x1111, 0x2222, 0x3333, 0x4444]);
+        icache.isolated_store(0x0000_0104, 0x5555);
+        assert_eq!(icache.isolated_load(0x0000_0104), 0x5555);
+        assert_eq!(icache.fetch(0x8000_0104), Some(0x5555));
+    }
+
+    #[test]
+    fn test_lock_mode_loads_line() {
+        let mut icache = ICache::new();
+        icache.configure(&ctrl(CACHE_CTRL_IS1 | CACHE_CTRL_LOCK));
+        icache.isolated_store(0x8000_0200, 0x1234);
+        icache.configure(&ctrl(CACHE_CTRL_IS1));
+        assert_eq!(icache.fetch(0x8000_0200), Some(0x1234));
+        assert_eq!(icache.fetch(0x8000_0204), None);
+    }
+}
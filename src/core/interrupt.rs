@@ -0,0 +1,123 @@
+use super::ioport::*;
+
+use serde::{Deserialize, Serialize};
+
+pub const IRQ_VBLANK: u8 = 0;
+pub const IRQ_GPU: u8 = 1;
+pub const IRQ_CDROM: u8 = 2;
+pub const IRQ_DMA: u8 = 3;
+pub const IRQ_TIMER0: u8 = 4;
+pub const IRQ_TIMER1: u8 = 5;
+pub const IRQ_TIMER2: u8 = 6;
+pub const IRQ_JOY_MCD: u8 = 7;
+pub const IRQ_SIO: u8 = 8;
+pub const IRQ_SPU: u8 = 9;
+pub const IRQ_LIGHTPEN: u8 = 10;
+
+const IRQ_LINES_MASK: u16 = 0x07FF;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InterruptController {
+    // I_STAT: latched requests, cleared by the CPU
+    stat: u16,
+    // I_MASK
+    mask: u16,
+    // current level of each device line, requests are latched on the rising edge
+    lines: u16,
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            stat: 0,
+            mask: 0,
+            lines: 0,
+        }
+    }
+
+    // drive a level-sensitive device line
+    pub fn set_line(&mut self, irq: u8, asserted: bool) {
+        let bit = 1 << irq;
+        if asserted {
+            if self.lines & bit == 0 {
+                self.stat |= bit;
+            }
+            self.lines |= bit;
+        } else {
+            self.lines &= !bit;
+        }
+    }
+
+    // a single pulse on a device line
+    pub fn raise(&mut self, irq: u8) {
+        self.stat |= 1 << irq;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.stat & self.mask != 0
+    }
+
+    pub fn load(&self, addr: u32) -> Result<u32, String> {
+        match addr {
+            IO_I_STAT => Ok(self.stat as u32),
+            IO_I_MASK => Ok(self.mask as u32),
+            _ => Err(format!("INTC: Unexpected read at 0x{:08X}", addr)),
+        }
+    }
+
+    pub fn store(&mut self, addr: u32, val: u32) -> Result<(), String> {
+        match addr {
+            IO_I_STAT => {
+                // writing 0 acknowledges the request, 1 leaves it untouched
+                self.stat &= val as u16;
+                Ok(())
+            }
+            IO_I_MASK => {
+                self.mask = val as u16 & IRQ_LINES_MASK;
+                Ok(())
+            }
+            _ => Err(format!("INTC: Unexpected write at 0x{:08X}", addr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acknowledge() {
+        let mut intc = InterruptController::new();
+        intc.raise(IRQ_VBLANK);
+        intc.raise(IRQ_TIMER2);
+        assert_eq!(intc.load(IO_I_STAT).unwrap(), 0x0041);
+        assert!(!intc.pending());
+        intc.store(IO_I_MASK, 0xFFFF_0040).unwrap();
+        assert_eq!(intc.load(IO_I_MASK).unwrap(), 0x0040);
+        assert!(intc.pending());
+        // acknowledge timer 2 only
+        intc.store(IO_I_STAT, !0x0040).unwrap();
+        assert_eq!(intc.load(IO_I_STAT).unwrap(), 0x0001);
+        assert!(!intc.pending());
+    }
+
+    #[test]
+    fn test_line_edge() {
+        let mut intc = InterruptController::new();
+        intc.set_line(IRQ_CDROM, true);
+        assert_eq!(intc.load(IO_I_STAT).unwrap(), 0x0004);
+        intc.store(IO_I_STAT, 0).unwrap();
+        // still asserted, but no new edge
+        intc.set_line(IRQ_CDROM, true);
+        assert_eq!(intc.load(IO_I_STAT).unwrap(), 0x0000);
+        intc.set_line(IRQ_CDROM, false);
+        intc.set_line(IRQ_CDROM, true);
+        assert_eq!(intc.load(IO_I_STAT).unwrap(), 0x0004);
+    }
+}
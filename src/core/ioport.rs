@@ -1,7 +1,9 @@
 use clap::builder::Str;
 use serde::{Deserialize, Serialize};
 
-use super::{timers::TimerPort, Machine, MachineMutation};
+use super::{
+    dma::Dma, mem_op_mask, timers::TimerPort, Machine, MachineMutation, MemOpSize, IRQ_GPU,
+};
 
 pub const IO_EXP1_BASE_ADDR: u32 = 0x0000;
 pub const IO_EXP2_BASE_ADDR: u32 = 0x0004;
@@ -58,14 +60,14 @@ pub const IO_TMR_HRETRACE_MAX: u32 = 0x0118;
 pub const IO_TMR_SYSCLOCK_VAL: u32 = 0x0120;
 pub const IO_TMR_SYSCLOCK_MODE: u32 = 0x0124;
 pub const IO_TMR_SYSCLOCK_MAX: u32 = 0x0128;
-pub const IO_CDROM_REG0: u32 = 0x0180;
-pub const IO_CDROM_REG1: u32 = 0x0181;
-pub const IO_CDROM_REG2: u32 = 0x0182;
-pub const IO_CDROM_REG3: u32 = 0x0183;
-pub const IO_GPU_REG0: u32 = 0x0180;
-pub const IO_GPU_REG1: u32 = 0x0184;
-pub const IO_MDEC_REG0: u32 = 0x0180;
-pub const IO_MDEC_REG1: u32 = 0x0184;
+pub const IO_CDROM_REG0: u32 = 0x0800;
+pub const IO_CDROM_REG1: u32 = 0x0801;
+pub const IO_CDROM_REG2: u32 = 0x0802;
+pub const IO_CDROM_REG3: u32 = 0x0803;
+pub const IO_GPU_REG0: u32 = 0x0810;
+pub const IO_GPU_REG1: u32 = 0x0814;
+pub const IO_MDEC_REG0: u32 = 0x0820;
+pub const IO_MDEC_REG1: u32 = 0x0824;
 pub const IO_VOICE_00_LEFT_RIGHT: u32 = 0x0C00;
 pub const IO_VOICE_00_ADPCM_SAMPLE_RATE: u32 = 0x0C04;
 pub const IO_VOICE_00_ADPCM_START_ADDR: u32 = 0x0C06;
@@ -235,51 +237,71 @@ pub const IO_EXT_VOL_R: u32 = 0x0DB6;
 pub const IO_CURR_MAIN_VOL_L: u32 = 0x0DB8;
 pub const IO_CURR_MAIN_VOL_R: u32 = 0x0DBA;
 pub const IO_SPU_UNKN_1DBC: u32 = 0x0DBC;
-pub const IO_DEBUG_PORT: u32 = 0x1041;
 
 pub const IO_SPU_BASE: u32 = 0x0C00;
 pub const IO_SPU_SIZE: u32 = 0x0200;
 
-pub const IO_REG_SIZE: u32 = 0x01080;
+pub const IO_REG_SIZE: u32 = 0x01000;
 
 struct IoPortHandler {
-    load: fn(&Machine, &mut MachineMutation, u32) -> Result<u32, String>,
-    store: fn(&mut Machine, u32, u32) -> Result<(), String>,
+    // native register width, narrower or wider accesses are split or shifted into its lanes
+    width: MemOpSize,
+    load: fn(&Machine, &mut MachineMutation, u32, MemOpSize) -> Result<u32, String>,
+    store: fn(&mut Machine, u32, u32, MemOpSize) -> Result<(), String>,
 }
 
-const REG_HANDLER: IoPortHandler = IoPortHandler {
-    load: |m: &Machine, mu: &mut MachineMutation, addr: u32| Ok(m.io.regs[addr as usize]),
-    store: |m: &mut Machine, addr: u32, val: u32| {
-        m.io.regs[addr as usize] = val;
+const MEMCTRL_HANDLER: IoPortHandler = IoPortHandler {
+    width: MemOpSize::Word,
+    load: |m: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| m.memctrl.load(addr),
+    store: |m: &mut Machine, addr: u32, val: u32, _: MemOpSize| {
+        m.memctrl.store(addr, val)?;
+        m.bus.configure(&m.cache_ctrl, &m.memctrl);
         Ok(())
     },
 };
 
-const INTERRUPT_HANDLER: IoPortHandler = IoPortHandler {
-    load: |m: &Machine, mu: &mut MachineMutation, addr: u32| match addr {
-        IO_I_STAT => Ok(m.cop0.int_stat()),
-        IO_I_MASK => Ok(m.cop0.int_mask()),
-        _ => Err("Unexpected read from interrupt port".to_string()),
+const DMA_HANDLER: IoPortHandler = IoPortHandler {
+    width: MemOpSize::Word,
+    load: |m: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| m.dma.load(addr),
+    store: |m: &mut Machine, addr: u32, val: u32, _: MemOpSize| {
+        m.dma.store(addr, val)?;
+        Dma::run(m);
+        Ok(())
     },
-    store: |m: &mut Machine, addr: u32, val: u32| match addr {
-        IO_I_STAT => {
-            m.cop0.int_set_stat(val);
-            Ok(())
-        }
-        IO_I_MASK => {
-            m.cop0.int_set_mask(val);
-            Ok(())
+};
+
+const GPU_HANDLER: IoPortHandler = IoPortHandler {
+    width: MemOpSize::Word,
+    load: |m: &Machine, mu: &mut MachineMutation, addr: u32, _: MemOpSize| {
+        // GPUREAD advances through a VRAM to CPU copy
+        if addr == IO_GPU_REG0 {
+            mu.gpu_read = true;
         }
-        _ => Err("Unexpected write to interrupt port".to_string()),
+        m.gpu.load(addr, m.clock)
     },
+    store: |m: &mut Machine, addr: u32, val: u32, _: MemOpSize| {
+        m.gpu.store(addr, val)?;
+        m.intc.set_line(IRQ_GPU, m.gpu.irq_line());
+        Ok(())
+    },
+};
+
+const INTC_HANDLER: IoPortHandler = IoPortHandler {
+    width: MemOpSize::Word,
+    load: |m: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| m.intc.load(addr),
+    store: |m: &mut Machine, addr: u32, val: u32, _: MemOpSize| m.intc.store(addr, val),
 };
 
+// 16-bit registers in 32-bit slots, the upper half reads as 0
 const TIMER_HANDLER: IoPortHandler = IoPortHandler {
-    load: |m: &Machine, mu: &mut MachineMutation, addr: u32| {
+    width: MemOpSize::Word,
+    load: |m: &Machine, mu: &mut MachineMutation, addr: u32, _: MemOpSize| {
+        // the timers are only caught up lazily
+        let timers = m.timers.peek(m.clock);
         let port: &dyn TimerPort = match (addr >> 4) & 0x0F {
-            0 => &m.timers.dotclock,
-            1 => &m.timers.hretrace,
-            2 => &m.timers.sysclock,
+            0 => &timers.dotclock,
+            1 => &timers.hretrace,
+            2 => &timers.sysclock,
             _ => return Err(format!("Unhandled timer port read at 0x{:08X}", addr)),
         };
         match addr & 0x0F {
@@ -292,7 +314,8 @@ const TIMER_HANDLER: IoPortHandler = IoPortHandler {
             _ => Err(format!("Unhandled timer port read at 0x{:08X}", addr)),
         }
     },
-    store: |m: &mut Machine, addr: u32, val: u32| {
+    store: |m: &mut Machine, addr: u32, val: u32, _: MemOpSize| {
+        m.sync_timers();
         let port: &mut dyn TimerPort = match (addr >> 4) & 0x0F {
             0 => &mut m.timers.dotclock,
             1 => &mut m.timers.hretrace,
@@ -300,70 +323,177 @@ const TIMER_HANDLER: IoPortHandler = IoPortHandler {
             _ => return Err(format!("Unhandled timer port write at 0x{:08X}", addr)),
         };
         match addr & 0x0F {
-            0 => Ok(port.write_count(val)),
-            4 => Ok(port.write_mode(val)),
-            8 => Ok(port.write_target(val)),
+            0 => port.write_count(val),
+            4 => port.write_mode(val),
+            8 => port.write_target(val),
             _ => return Err(format!("Unhandled timer port write at 0x{:08X}", addr)),
         }
-    },
-};
-
-const DEBUG_HANDLER: IoPortHandler = IoPortHandler {
-    load: |m: &Machine, mu: &mut MachineMutation, addr: u32| {
-        Err("Unexpected read from debug port".to_string())
-    },
-    store: |m: &mut Machine, addr: u32, val: u32| {
-        println!("DEBUG_WRITE: {:08X}", val);
+        m.schedule_timers();
         Ok(())
     },
 };
 
 const SPU_HANDLER: IoPortHandler = IoPortHandler {
-    load: |m: &Machine, mu: &mut MachineMutation, addr: u32| m.spu.load(addr),
-    store: |m: &mut Machine, addr: u32, val: u32| m.spu.store(addr, val),
+    width: MemOpSize::Half,
+    load: |m: &Machine, _: &mut MachineMutation, addr: u32, _: MemOpSize| m.spu.load(addr),
+    store: |m: &mut Machine, addr: u32, val: u32, _: MemOpSize| m.spu.store(addr, val),
 };
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct IoPort {
-    regs: Vec<u32>,
-}
+pub struct IoPort {}
 
 impl IoPort {
     pub fn new() -> IoPort {
-        IoPort {
-            regs: vec![0; IO_REG_SIZE as usize],
-        }
+        IoPort {}
     }
 
     fn lookup_handler(addr: u32) -> Option<&'static IoPortHandler> {
         println!("lookup_handler: {:08X}", addr);
-        match addr {
+        match addr & !3 {
             IO_EXP1_BASE_ADDR | IO_EXP2_BASE_ADDR | IO_EXP1_DELAY_SIZE | IO_EXP3_DELAY_SIZE
             | IO_BIOS_ROM | IO_SPU_DELAY | IO_CDROM_DELAY | IO_EXP2_DELAY_SIZE
-            | IO_COMMON_DELAY | IO_RAM_SIZE => Some(&REG_HANDLER),
-            IO_I_MASK | IO_I_STAT => Some(&INTERRUPT_HANDLER),
+            | IO_COMMON_DELAY | IO_RAM_SIZE => Some(&MEMCTRL_HANDLER),
+            IO_I_STAT | IO_I_MASK => Some(&INTC_HANDLER),
+            IO_GPU_REG0 | IO_GPU_REG1 => Some(&GPU_HANDLER),
+            IO_DMA_MDEC_IN_MADR..=IO_DMA_OTC_CHCR | IO_DMA_DPCR | IO_DMA_DICR => Some(&DMA_HANDLER),
             IO_TMR_DOTCLOCK_VAL | IO_TMR_DOTCLOCK_MODE | IO_TMR_DOTCLOCK_MAX
             | IO_TMR_HRETRACE_VAL | IO_TMR_HRETRACE_MODE | IO_TMR_HRETRACE_MAX
             | IO_TMR_SYSCLOCK_VAL | IO_TMR_SYSCLOCK_MODE | IO_TMR_SYSCLOCK_MAX => {
                 Some(&TIMER_HANDLER)
             }
-            IO_DEBUG_PORT => Some(&DEBUG_HANDLER),
-            addr if addr >= IO_SPU_BASE && addr < IO_SPU_BASE + IO_SPU_SIZE => Some(&SPU_HANDLER),
+            _ if (IO_SPU_BASE..IO_SPU_BASE + IO_SPU_SIZE).contains(&addr) => Some(&SPU_HANDLER),
             _ => None,
         }
     }
 
-    pub fn load(m: &Machine, mu: &mut MachineMutation, addr: u32) -> Result<u32, String> {
-        match IoPort::lookup_handler(addr) {
-            Some(handler) => (handler.load)(m, mu, addr),
-            None => Err(format!("Unhandled IO read at 0x{:08X}", addr)),
+    pub fn load(
+        m: &Machine,
+        mu: &mut MachineMutation,
+        addr: u32,
+        size: MemOpSize,
+    ) -> Result<u32, String> {
+        let handler = match IoPort::lookup_handler(addr) {
+            Some(handler) => handler,
+            // registers without a device read as 0
+            None => {
+                println!("WARN: Unhandled IO read at 0x{:08X}", addr);
+                return Ok(0);
+            }
+        };
+        let val = match (handler.width, size) {
+            (MemOpSize::Byte, _) => (handler.load)(m, mu, addr, size)?,
+            // 32-bit reads of 16-bit registers combine two of them
+            (MemOpSize::Half, MemOpSize::Word) => {
+                let lo = (handler.load)(m, mu, addr, MemOpSize::Half)? & 0xFFFF;
+                let hi = (handler.load)(m, mu, addr + 2, MemOpSize::Half)? & 0xFFFF;
+                lo | (hi << 16)
+            }
+            (MemOpSize::Half, _) => (handler.load)(m, mu, addr & !1, size)? >> (8 * (addr & 1)),
+            // narrower reads see their lanes of the 32-bit register
+            (MemOpSize::Word, _) => (handler.load)(m, mu, addr & !3, size)? >> (8 * (addr & 3)),
+        };
+        Ok(val & mem_op_mask(size))
+    }
+
+    pub fn store(m: &mut Machine, addr: u32, val: u32, size: MemOpSize) -> Result<(), String> {
+        let handler = match IoPort::lookup_handler(addr) {
+            Some(handler) => handler,
+            None => {
+                println!("WARN: Unhandled IO write at 0x{:08X}", addr);
+                return Ok(());
+            }
+        };
+        let val = val & mem_op_mask(size);
+        match (handler.width, size) {
+            (MemOpSize::Byte, _) => (handler.store)(m, addr, val, size),
+            (MemOpSize::Half, MemOpSize::Word) => {
+                (handler.store)(m, addr, val & 0xFFFF, MemOpSize::Half)?;
+                (handler.store)(m, addr + 2, val >> 16, MemOpSize::Half)
+            }
+            // the data is driven on its byte lanes, the rest of the register gets zeros
+            (MemOpSize::Half, _) => {
+                (handler.store)(m, addr & !1, (val << (8 * (addr & 1))) & 0xFFFF, size)
+            }
+            (MemOpSize::Word, _) => {
+                let shift = 8 * (addr & 3);
+                let rest = IoPort::unwritten_lanes(m, addr & !3) & !(mem_op_mask(size) << shift);
+                (handler.store)(m, addr & !3, (val << shift) | rest, size)
+            }
         }
     }
 
-    pub fn store(m: &mut Machine, addr: u32, val: u32) -> Result<(), String> {
-        match IoPort::lookup_handler(addr) {
-            Some(handler) => (handler.store)(m, addr, val),
-            None => Err(format!("Unhandled IO write at 0x{:08X}", addr)),
+    // write-to-acknowledge registers keep their value in the lanes of a narrow store
+    fn unwritten_lanes(m: &Machine, addr: u32) -> u32 {
+        match addr {
+            // requests are acknowledged by writing 0
+            IO_I_STAT => m.intc.load(IO_I_STAT).unwrap_or(0),
+            // flags are acknowledged by writing 1
+            IO_DMA_DICR => m.dma.dicr_control(),
+            _ => 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(m: &Machine, addr: u32, size: MemOpSize) -> u32 {
+        IoPort::load(m, &mut MachineMutation::new(), addr, size).unwrap()
+    }
+
+    #[test]
+    fn test_word_register_lanes() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        IoPort::store(&mut m, IO_I_MASK, 0xFFFF_0405, MemOpSize::Word).unwrap();
+        assert_eq!(load(&m, IO_I_MASK + 1, MemOpSize::Byte), 0x04);
+        assert_eq!(load(&m, IO_I_MASK + 2, MemOpSize::Half), 0x0000);
+        // only the low half of the register is written by SH
+        IoPort::store(&mut m, IO_I_MASK, 0x1234_0003, MemOpSize::Half).unwrap();
+        assert_eq!(load(&m, IO_I_MASK, MemOpSize::Word), 0x0003);
+        // a byte on lane 1 lands in bits 8-15
+        IoPort::store(
+            &mut m,
+            IO_TMR_HRETRACE_MAX + 1,
+            0x0000_0012,
+            MemOpSize::Byte,
+        )
+        .unwrap();
+        assert_eq!(load(&m, IO_TMR_HRETRACE_MAX, MemOpSize::Word), 0x1200);
+    }
+
+    #[test]
+    fn test_unhandled_register() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        IoPort::store(&mut m, IO_SIO_DATA, 0xFFFF_FFFF, MemOpSize::Word).unwrap();
+        assert_eq!(load(&m, IO_SIO_DATA, MemOpSize::Word), 0);
+    }
+
+    #[test]
+    fn test_narrow_acknowledge() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        m.intc.raise(0);
+        m.intc.raise(9);
+        // acknowledges IRQ9 only, IRQ0 is in the lane not written
+        IoPort::store(&mut m, IO_I_STAT + 1, 0xFD, MemOpSize::Byte).unwrap();
+        assert_eq!(load(&m, IO_I_STAT, MemOpSize::Word), 0x0001);
+
+        IoPort::store(&mut m, IO_DMA_DICR, 0x00FF_0000, MemOpSize::Word).unwrap();
+        // a byte on the flag lane leaves the enables alone
+        IoPort::store(&mut m, IO_DMA_DICR + 3, 0x7F, MemOpSize::Byte).unwrap();
+        assert_eq!(load(&m, IO_DMA_DICR, MemOpSize::Word), 0x00FF_0000);
+    }
+
+    #[test]
+    fn test_half_register_lanes() {
+        let mut m = Machine::new(vec![0; 0x80000]);
+        // a word write covers two 16-bit SPU registers
+        IoPort::store(&mut m, IO_SPU_MAIN_VOL_L, 0x2222_1111, MemOpSize::Word).unwrap();
+        assert_eq!(load(&m, IO_SPU_MAIN_VOL_L, MemOpSize::Half), 0x1111);
+        assert_eq!(load(&m, IO_SPU_MAIN_VOL_R, MemOpSize::Half), 0x2222);
+        assert_eq!(load(&m, IO_SPU_MAIN_VOL_L, MemOpSize::Word), 0x2222_1111);
+        assert_eq!(load(&m, IO_SPU_MAIN_VOL_R + 1, MemOpSize::Byte), 0x22);
+        IoPort::store(&mut m, IO_SPU_MAIN_VOL_L + 1, 0x55, MemOpSize::Byte).unwrap();
+        assert_eq!(load(&m, IO_SPU_MAIN_VOL_L, MemOpSize::Half), 0x5500);
+    }
+}
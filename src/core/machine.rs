@@ -1,8 +1,21 @@
-use std::rc::Rc;
+use std::{any::Any, rc::Rc};
 
 use super::{
-    bus::Bus, ioport::IoPort, spu::Spu, timers::Timers, Cop0, Cop0ExceptionParams, CpuInstEntry,
-    CpuSlow, MemOpSize,
+    bus::Bus,
+    cache_ctrl::CacheControl,
+    cartridge::Cartridge,
+    device::{BusDevice, DeviceId, DeviceState, Devices},
+    dma::Dma,
+    exp2::Exp2,
+    gpu::Gpu,
+    icache::ICache,
+    interrupt::InterruptController,
+    ioport::IoPort,
+    memctrl::MemoryControl,
+    scheduler::{Scheduler, SchedulerEvent},
+    spu::Spu,
+    timers::Timers,
+    Cop0, Cop0ExceptionParams, CpuInstEntry, CpuSlow, MemOpSize, IRQ_VBLANK,
 };
 use rand::{rngs::SmallRng, RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
@@ -11,12 +24,24 @@ pub struct Machine {
     pub bios: Vec<u8>,
     pub ram: Vec<u8>,
     pub dcache: Vec<u8>,
+    pub icache: ICache,
+    pub cache_ctrl: CacheControl,
+    pub memctrl: MemoryControl,
     pub bus: Bus,
     pub cop0: Cop0,
     pub cpu: CpuSlow,
+    pub intc: InterruptController,
     pub io: IoPort,
+    pub dma: Dma,
+    pub gpu: Gpu,
     pub spu: Spu,
     pub timers: Timers,
+    pub scheduler: Scheduler,
+    pub cart: Cartridge,
+    pub exp2: Exp2,
+    pub devices: Devices,
+    // system clocks elapsed since power on
+    pub clock: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -24,11 +49,23 @@ pub struct MachineState {
     pub bios: Vec<u8>,
     pub ram: Vec<u8>,
     pub dcache: Vec<u8>,
+    pub icache: ICache,
+    pub cache_ctrl: CacheControl,
+    pub memctrl: MemoryControl,
     pub cpu: CpuSlow,
     pub cop0: Cop0,
+    pub intc: InterruptController,
     pub io: IoPort,
+    pub dma: Dma,
+    pub gpu: Gpu,
     pub spu: Spu,
     pub timers: Timers,
+    pub scheduler: Scheduler,
+    pub clock: u64,
+    pub cart: Cartridge,
+    pub exp2: Exp2,
+    #[serde(default)]
+    pub devices: Vec<DeviceState>,
 }
 
 impl Machine {
@@ -38,15 +75,28 @@ impl Machine {
             bios,
             ram: vec![0; 0x20_0000],
             dcache: vec![0; 0x400],
+            icache: ICache::new(),
+            cache_ctrl: CacheControl::new(),
+            memctrl: MemoryControl::new(),
             bus: Bus::new(),
             cop0: Cop0::new(),
             cpu: CpuSlow::new(),
+            intc: InterruptController::new(),
             io: IoPort::new(),
+            dma: Dma::new(),
+            gpu: Gpu::new(),
             spu: Spu::new(),
             timers: Timers::new(),
+            scheduler: Scheduler::new(),
+            cart: Cartridge::new(),
+            exp2: Exp2::new(),
+            devices: Devices::new(),
+            clock: 0,
         };
         rng.fill_bytes(m.ram.as_mut_slice());
         rng.fill_bytes(m.dcache.as_mut_slice());
+        let vblank = m.gpu.frame_clocks();
+        m.scheduler.schedule(SchedulerEvent::VBlank, vblank);
         m
     }
 
@@ -55,33 +105,129 @@ impl Machine {
             bios: self.bios.clone(),
             ram: self.ram.clone(),
             dcache: self.dcache.clone(),
+            icache: self.icache.clone(),
+            cache_ctrl: self.cache_ctrl.clone(),
+            memctrl: self.memctrl.clone(),
             cpu: self.cpu.clone(),
             cop0: self.cop0.clone(),
+            intc: self.intc.clone(),
             io: self.io.clone(),
+            dma: self.dma.clone(),
+            gpu: self.gpu.clone(),
             spu: self.spu.clone(),
             timers: self.timers.clone(),
+            scheduler: self.scheduler.clone(),
+            clock: self.clock,
+            cart: self.cart.clone(),
+            exp2: self.exp2.clone(),
+            devices: self.devices.save_state(),
         }
     }
 
-    pub fn load_state(&mut self, state: MachineState) {
+    pub fn load_state(&mut self, state: MachineState) -> Result<(), String> {
         self.bios = state.bios;
         self.ram = state.ram;
         self.dcache = state.dcache;
+        self.icache = state.icache;
+        self.cache_ctrl = state.cache_ctrl;
+        self.memctrl = state.memctrl;
+        self.bus.configure(&self.cache_ctrl, &self.memctrl);
         self.cpu = state.cpu;
         self.cop0 = state.cop0;
+        self.intc = state.intc;
+        self.io = state.io;
+        self.dma = state.dma;
+        self.gpu = state.gpu;
+        self.spu = state.spu;
+        self.timers = state.timers;
+        self.scheduler = state.scheduler;
+        self.clock = state.clock;
+        self.cart = state.cart;
+        self.exp2 = state.exp2;
+        self.devices.load_state(&state.devices)
     }
 
     pub fn reset(&mut self) {
         self.cpu.reset();
+        self.devices.reset();
+    }
+
+    // parallel port ROM image, mapped in EXP1
+    pub fn insert_cartridge(&mut self, rom: Vec<u8>) -> Result<(), String> {
+        self.cart.insert(rom)
+    }
+
+    // last code shown on the POST display, tracks the BIOS boot progress
+    pub fn post_code(&self) -> u8 {
+        self.exp2.post()
+    }
+
+    // kernel TTY on DUART channel A
+    pub fn take_tty_output(&mut self) -> Vec<u8> {
+        self.exp2.duart.take_output(0)
+    }
+
+    pub fn push_tty_input(&mut self, bytes: &[u8]) {
+        self.exp2.duart.push_input(0, bytes);
+    }
+
+    pub fn commit_exp2_load(&mut self, mu: &MachineMutation) {
+        if let Some((addr, bytes)) = mu.exp2_load {
+            self.exp2.commit_load(addr, bytes);
+        }
+    }
+
+    // maps `device` at the physical range in KUSEG, KSEG0 and KSEG1, ahead of the built in regions
+    pub fn attach_device(
+        &mut self,
+        base: u32,
+        size: u32,
+        device: Box<dyn BusDevice>,
+    ) -> Result<DeviceId, String> {
+        let id = self.devices.attach(base, size, device)?;
+        self.bus.set_external(self.devices.ranges());
+        self.bus.configure(&self.cache_ctrl, &self.memctrl);
+        Ok(id)
+    }
+
+    pub fn device<T: BusDevice>(&self, id: DeviceId) -> Option<&T> {
+        (self.devices.get(id)? as &dyn Any).downcast_ref()
+    }
+
+    pub fn device_mut<T: BusDevice>(&mut self, id: DeviceId) -> Option<&mut T> {
+        (self.devices.get_mut(id)? as &mut dyn Any).downcast_mut()
+    }
+
+    pub fn commit_device_load(&mut self, mu: &MachineMutation) {
+        if let Some((id, offset, size)) = mu.device_load {
+            if let Some(device) = self.devices.get_mut(id) {
+                device.commit_load(offset, size);
+            }
+        }
     }
 
     pub fn cycle(&mut self) -> Result<(), String> {
         let mut mu = MachineMutation::new();
 
-        self.cop0.cycle();
+        self.cop0.cycle(self.intc.pending());
         self.cpu.cycle(self, &mut mu)?;
 
-        self.timers.mutate(&mut mu);
+        // the mode was read at the start of the instruction, flags raised later are kept
+        if let Some(addr) = mu.timer_mode_read {
+            self.sync_timers();
+            self.timers.commit_read_mode(addr);
+        }
+
+        // the instruction's clocks have elapsed by the time its results are written back
+        self.clock += mu.cycles as u64;
+        self.run_events();
+
+        self.icache.mutate(&mu);
+        self.commit_device_load(&mu);
+        self.commit_exp2_load(&mu);
+        if mu.gpu_read {
+            self.gpu.commit_read();
+        }
         Bus::mutate(self, &mut mu)?;
         // 例外処理のためcop0がcpuより先
         self.cop0.mutate(&mut mu)?;
@@ -89,19 +235,67 @@ impl Machine {
 
         Ok(())
     }
+
+    fn run_events(&mut self) {
+        while let Some(event) = self.scheduler.pop_due(self.clock) {
+            match event {
+                SchedulerEvent::Timers => {
+                    self.sync_timers();
+                    self.schedule_timers();
+                }
+                SchedulerEvent::Dma => Dma::complete_due(self),
+                SchedulerEvent::VBlank => {
+                    self.gpu.vblank(self.clock);
+                    self.intc.raise(IRQ_VBLANK);
+                    let next = self.clock + self.gpu.frame_clocks();
+                    self.scheduler.schedule(SchedulerEvent::VBlank, next);
+                }
+            }
+        }
+    }
+
+    // must be called before touching the timers
+    pub fn sync_timers(&mut self) {
+        self.timers.sync(self.clock, &mut self.intc);
+    }
+
+    pub fn schedule_timers(&mut self) {
+        match self.timers.next_event() {
+            Some(at) => self.scheduler.schedule(SchedulerEvent::Timers, at),
+            None => self.scheduler.cancel(SchedulerEvent::Timers),
+        }
+    }
 }
 
 pub struct MachineMutation {
     pub branch_target: Option<u32>,
     pub reg_write: Option<(u8, u32)>,
+    pub load_write: Option<(u8, u32)>,
     pub bus_write: Option<(u32, u32, MemOpSize)>,
+    // rest of a three byte SWL/SWR, stored after bus_write
+    pub bus_write_split: Option<(u32, u32, MemOpSize)>,
     pub cop0_write: Option<(u8, u32)>,
     pub hilo_write: Option<(u32, u32)>,
     pub cop0_exception: Option<Cop0ExceptionParams>,
     pub exception_branch: Option<u32>,
     pub exception_return: bool,
-    pub icache_write: Option<(u32, Rc<CpuInstEntry>)>,
+    pub decode_cache_write: Option<(u32, Rc<CpuInstEntry>)>,
+    pub icache_fill: Option<(u32, [u32; 4])>,
     pub timer_mode_read: Option<u32>,
+    pub device_load: Option<(DeviceId, u32, MemOpSize)>,
+    pub exp2_load: Option<(u32, u32)>,
+    // GPUREAD was loaded
+    pub gpu_read: bool,
+    // system clocks spent on the instruction, including stalls
+    pub cycles: u32,
+    pub hilo_ready: Option<u64>,
+    pub write_buffer_push: Option<u64>,
+}
+
+impl Default for MachineMutation {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MachineMutation {
@@ -109,14 +303,23 @@ impl MachineMutation {
         MachineMutation {
             branch_target: None,
             reg_write: None,
+            load_write: None,
             bus_write: None,
+            bus_write_split: None,
             cop0_write: None,
             hilo_write: None,
             cop0_exception: None,
             exception_branch: None,
             exception_return: false,
-            icache_write: None,
+            decode_cache_write: None,
+            icache_fill: None,
             timer_mode_read: None,
+            device_load: None,
+            exp2_load: None,
+            gpu_read: false,
+            cycles: 1,
+            hilo_ready: None,
+            write_buffer_push: None,
         }
     }
 }
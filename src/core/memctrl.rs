@@ -0,0 +1,231 @@
+use super::{ioport::*, MemOpSize};
+
+use serde::{Deserialize, Serialize};
+
+// devices timed by the delay/size registers, in register order
+pub const MEMCTRL_EXP1: usize = 0;
+pub const MEMCTRL_EXP3: usize = 1;
+pub const MEMCTRL_BIOS: usize = 2;
+pub const MEMCTRL_SPU: usize = 3;
+pub const MEMCTRL_CDROM: usize = 4;
+pub const MEMCTRL_EXP2: usize = 5;
+
+const DELAY_ACCESS_TIME_SHIFT: u32 = 4;
+const DELAY_USE_COM0: u32 = 0x0000_0100;
+const DELAY_USE_COM2: u32 = 0x0000_0400;
+const DELAY_USE_COM3: u32 = 0x0000_0800;
+const DELAY_BUS_16BIT: u32 = 0x0000_1000;
+const DELAY_SIZE_SHIFT: u32 = 16;
+
+const RAM_SIZE_WINDOW_SHIFT: u32 = 9;
+const RAM_CHIP_SIZE: u32 = 0x20_0000;
+
+// how the 8MB RAM window is split, selected by RAM_SIZE bits 9-11
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RamWindow {
+    // mirrored RAM, starting at 0
+    pub memory: u32,
+    // open bus after the memory part
+    pub high_z: u32,
+    // bus errors up to the end of the window
+    pub locked: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MemoryControl {
+    exp1_base: u32,
+    exp2_base: u32,
+    delay_size: [u32; 6],
+    com_delay: u32,
+    ram_size: u32,
+}
+
+impl MemoryControl {
+    pub fn new() -> MemoryControl {
+        // values the BIOS programs at boot
+        MemoryControl {
+            exp1_base: 0x1F00_0000,
+            exp2_base: 0x1F80_2000,
+            delay_size: [
+                0x0013_243F,
+                0x0000_3022,
+                0x0013_243F,
+                0x2009_31E1,
+                0x0002_0843,
+                0x0007_0777,
+            ],
+            com_delay: 0x0003_1125,
+            ram_size: 0x0000_0B88,
+        }
+    }
+
+    pub fn load(&self, addr: u32) -> Result<u32, String> {
+        match addr {
+            IO_EXP1_BASE_ADDR => Ok(self.exp1_base),
+            IO_EXP2_BASE_ADDR => Ok(self.exp2_base),
+            IO_EXP1_DELAY_SIZE => Ok(self.delay_size[MEMCTRL_EXP1]),
+            IO_EXP3_DELAY_SIZE => Ok(self.delay_size[MEMCTRL_EXP3]),
+            IO_BIOS_ROM => Ok(self.delay_size[MEMCTRL_BIOS]),
+            IO_SPU_DELAY => Ok(self.delay_size[MEMCTRL_SPU]),
+            IO_CDROM_DELAY => Ok(self.delay_size[MEMCTRL_CDROM]),
+            IO_EXP2_DELAY_SIZE => Ok(self.delay_size[MEMCTRL_EXP2]),
+            IO_COMMON_DELAY => Ok(self.com_delay),
+            IO_RAM_SIZE => Ok(self.ram_size),
+            _ => Err(format!("MEMCTRL: Unexpected read at 0x{:08X}", addr)),
+        }
+    }
+
+    pub fn store(&mut self, addr: u32, val: u32) -> Result<(), String> {
+        println!("MEMCTRL: {:08X} <= {:08X}", addr, val);
+        match addr {
+            // the upper 8 bits of the base addresses are fixed to 0x1F
+            IO_EXP1_BASE_ADDR => self.exp1_base = 0x1F00_0000 | (val & 0x00FF_FFFF),
+            IO_EXP2_BASE_ADDR => self.exp2_base = 0x1F00_0000 | (val & 0x00FF_FFFF),
+            IO_EXP1_DELAY_SIZE => self.delay_size[MEMCTRL_EXP1] = val,
+            IO_EXP3_DELAY_SIZE => self.delay_size[MEMCTRL_EXP3] = val,
+            IO_BIOS_ROM => self.delay_size[MEMCTRL_BIOS] = val,
+            IO_SPU_DELAY => self.delay_size[MEMCTRL_SPU] = val,
+            IO_CDROM_DELAY => self.delay_size[MEMCTRL_CDROM] = val,
+            IO_EXP2_DELAY_SIZE => self.delay_size[MEMCTRL_EXP2] = val,
+            IO_COMMON_DELAY => self.com_delay = val,
+            IO_RAM_SIZE => self.ram_size = val,
+            _ => return Err(format!("MEMCTRL: Unexpected write at 0x{:08X}", addr)),
+        }
+        Ok(())
+    }
+
+    // physical base and size of expansion region 1
+    pub fn exp1_region(&self) -> (u32, u32) {
+        (self.exp1_base, self.window_size(MEMCTRL_EXP1))
+    }
+
+    pub fn exp2_region(&self) -> (u32, u32) {
+        (self.exp2_base, self.window_size(MEMCTRL_EXP2))
+    }
+
+    fn window_size(&self, device: usize) -> u32 {
+        1 << ((self.delay_size[device] >> DELAY_SIZE_SHIFT) & 0x1F)
+    }
+
+    pub fn ram_window(&self) -> RamWindow {
+        const MB: u32 = 0x10_0000;
+        let (memory, high_z, locked) = match (self.ram_size >> RAM_SIZE_WINDOW_SHIFT) & 7 {
+            0 => (MB, 0, 7 * MB),
+            1 => (4 * MB, 0, 4 * MB),
+            2 => (MB, MB, 6 * MB),
+            3 => (4 * MB, 4 * MB, 0),
+            4 => (2 * MB, 0, 6 * MB),
+            6 => (2 * MB, 2 * MB, 4 * MB),
+            _ => (8 * MB, 0, 0),
+        };
+        RamWindow {
+            memory,
+            high_z,
+            locked,
+        }
+    }
+
+    // the 2MB of RAM is mirrored across the memory part of the window
+    pub fn ram_mask(&self) -> u32 {
+        self.ram_window().memory.min(RAM_CHIP_SIZE) - 1
+    }
+
+    // extra cycles spent on an access to a device, per the delay and COM_DELAY fields
+    pub fn access_time(&self, device: usize, size: MemOpSize) -> u32 {
+        let delay = self.delay_size[device];
+        let com = |n: u32| ((self.com_delay >> (n * 4)) & 0x0F) as i32;
+
+        let mut first = 0;
+        let mut seq = 0;
+        let mut min = 0;
+        if delay & DELAY_USE_COM0 != 0 {
+            first += com(0) - 1;
+            seq += com(0) - 1;
+        }
+        if delay & DELAY_USE_COM2 != 0 {
+            first += com(2);
+            seq += com(2);
+        }
+        if delay & DELAY_USE_COM3 != 0 {
+            min = com(3);
+        }
+        if first < 6 {
+            first += 1;
+        }
+
+        let access = ((delay >> DELAY_ACCESS_TIME_SHIFT) & 0x0F) as i32;
+        first = (first + access + 2).max(min + 6);
+        seq = (seq + access + 2).max(min + 2);
+
+        let bus_16bit = delay & DELAY_BUS_16BIT != 0;
+        let time = match size {
+            MemOpSize::Byte => first,
+            MemOpSize::Half if bus_16bit => first,
+            MemOpSize::Half => first + seq,
+            MemOpSize::Word if bus_16bit => first + seq,
+            MemOpSize::Word => first + 3 * seq,
+        };
+        (time - 1).max(0) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bios_timing() {
+        let memctrl = MemoryControl::new();
+        // 8-bit BIOS ROM with the boot settings
+        assert_eq!(memctrl.access_time(MEMCTRL_BIOS, MemOpSize::Byte), 6);
+        assert_eq!(memctrl.access_time(MEMCTRL_BIOS, MemOpSize::Half), 12);
+        assert_eq!(memctrl.access_time(MEMCTRL_BIOS, MemOpSize::Word), 24);
+        assert_eq!(memctrl.access_time(MEMCTRL_CDROM, MemOpSize::Word), 24);
+        // 16-bit SPU using the COM0 recovery period
+        assert_eq!(memctrl.access_time(MEMCTRL_SPU, MemOpSize::Half), 20);
+        assert_eq!(memctrl.access_time(MEMCTRL_SPU, MemOpSize::Word), 40);
+    }
+
+    #[test]
+    fn test_ram_window() {
+        let mut memctrl = MemoryControl::new();
+        assert_eq!(memctrl.exp1_region(), (0x1F00_0000, 0x8_0000));
+        assert_eq!(memctrl.ram_mask(), 0x1F_FFFF);
+        memctrl.store(IO_RAM_SIZE, 0x0000_0488).unwrap();
+        assert_eq!(memctrl.ram_mask(), 0x0F_FFFF);
+        memctrl.store(IO_EXP1_BASE_ADDR, 0x0000_1000).unwrap();
+        assert_eq!(memctrl.load(IO_EXP1_BASE_ADDR).unwrap(), 0x1F00_1000);
+    }
+
+    #[test]
+    fn test_ram_window_settings() {
+        const MB: u32 = 0x10_0000;
+        // memory, HighZ and locked sizes for RAM_SIZE bits 9-11
+        let table = [
+            (MB, 0, 7 * MB),
+            (4 * MB, 0, 4 * MB),
+            (MB, MB, 6 * MB),
+            (4 * MB, 4 * MB, 0),
+            (2 * MB, 0, 6 * MB),
+            (8 * MB, 0, 0),
+            (2 * MB, 2 * MB, 4 * MB),
+            (8 * MB, 0, 0),
+        ];
+        let mut memctrl = MemoryControl::new();
+        for (i, (memory, high_z, locked)) in table.into_iter().enumerate() {
+            memctrl
+                .store(IO_RAM_SIZE, 0x0000_0088 | (i as u32) << 9)
+                .unwrap();
+            assert_eq!(
+                memctrl.ram_window(),
+                RamWindow {
+                    memory,
+                    high_z,
+                    locked
+                },
+                "RAM_SIZE setting {}",
+                i
+            );
+        }
+    }
+}
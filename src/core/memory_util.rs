@@ -5,6 +5,22 @@ pub enum MemOpSize {
     Word,
 }
 
+pub fn mem_op_mask(size: MemOpSize) -> u32 {
+    match size {
+        MemOpSize::Byte => 0xFF,
+        MemOpSize::Half => 0xFFFF,
+        MemOpSize::Word => 0xFFFF_FFFF,
+    }
+}
+
+pub fn mem_op_bytes(size: MemOpSize) -> u32 {
+    match size {
+        MemOpSize::Byte => 1,
+        MemOpSize::Half => 2,
+        MemOpSize::Word => 4,
+    }
+}
+
 pub fn mem_store(mem: &mut [u8], addr: u32, val: u32, size: MemOpSize) {
     match size {
         MemOpSize::Byte => mem_store_u8(mem, addr, val as u8),
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum SchedulerEvent {
+    // a timer reaches its target or 0xFFFF with an IRQ enabled
+    Timers,
+    // a DMA transfer running alongside the CPU completes
+    Dma,
+    // start of the vertical blanking, once per frame
+    VBlank,
+}
+
+// devices register the clock of their next interesting event, and are caught up when it expires
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Scheduler {
+    events: Vec<(SchedulerEvent, u64)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler { events: vec![] }
+    }
+
+    // replaces any pending occurrence of the event
+    pub fn schedule(&mut self, event: SchedulerEvent, at: u64) {
+        self.cancel(event);
+        self.events.push((event, at));
+    }
+
+    pub fn cancel(&mut self, event: SchedulerEvent) {
+        self.events.retain(|&(e, _)| e != event);
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.events.iter().map(|&(_, at)| at).min()
+    }
+
+    // removes and returns the earliest event due at `now`
+    pub fn pop_due(&mut self, now: u64) -> Option<SchedulerEvent> {
+        let (idx, &(event, at)) = self
+            .events
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &(_, at))| at)?;
+        if at > now {
+            return None;
+        }
+        self.events.swap_remove(idx);
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pop_due() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(SchedulerEvent::Timers, 100);
+        scheduler.schedule(SchedulerEvent::Timers, 50);
+        assert_eq!(scheduler.next_deadline(), Some(50));
+        assert_eq!(scheduler.pop_due(49), None);
+        assert_eq!(scheduler.pop_due(60), Some(SchedulerEvent::Timers));
+        assert_eq!(scheduler.pop_due(200), None);
+    }
+}
@@ -22,6 +22,10 @@ impl Spu {
 
     pub fn load(&self, addr: u32) -> Result<u32, String> {
         match addr {
+            IO_SPU_MAIN_VOL_L => Ok(self.master_volume_left as u32),
+            IO_SPU_MAIN_VOL_R => Ok(self.master_volume_right as u32),
+            IO_SPU_REVERB_OUT_L => Ok(self.reverb_volume_left as u32),
+            IO_SPU_REVERB_OUT_R => Ok(self.reverb_volume_right as u32),
             _ => Err(format!("SPU: Unimplemented load at 0x{:08X}", addr)),
         }
     }
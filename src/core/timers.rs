@@ -22,13 +22,15 @@ use timer_dotclock::DotclockSource;
 use timer_hretrace::HretraceSource;
 use timer_sysclock::SysclockSource;
 
-use super::MachineMutation;
+use super::{InterruptController, IRQ_TIMER0, IRQ_TIMER1, IRQ_TIMER2};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Timers {
     pub dotclock: Timer<DotclockSource>,
     pub hretrace: Timer<HretraceSource>,
     pub sysclock: Timer<SysclockSource>,
+    // system clock the timers have been caught up to
+    last_sync: u64,
 }
 
 impl Timers {
@@ -37,22 +39,94 @@ impl Timers {
             dotclock: timer_dotclock::new_dotclock_timer(),
             hretrace: timer_hretrace::new_hretrace_timer(),
             sysclock: timer_sysclock::new_sysclock_timer(),
+            last_sync: 0,
         }
     }
 
+    // catch up to `now`, returns which timers raised an interrupt
+    fn advance(&mut self, now: u64) -> [bool; 3] {
+        let ticks = now.saturating_sub(self.last_sync);
+        self.last_sync = self.last_sync.max(now);
+        [
+            self.dotclock.advance(ticks),
+            self.hretrace.advance(ticks),
+            self.sysclock.advance(ticks),
+        ]
+    }
+
     // must be called before any write
-    pub fn mutate(&mut self, mu: &MachineMutation) {
-        self.dotclock.mutate();
-        self.hretrace.mutate();
-        self.sysclock.mutate();
-        match mu.timer_mode_read {
-            Some(addr) => match (addr >> 4) & 0x0F {
-                0 => self.dotclock.commit_read_mode(),
-                1 => self.hretrace.commit_read_mode(),
-                2 => self.sysclock.commit_read_mode(),
-                _ => {}
-            },
+    pub fn sync(&mut self, now: u64, intc: &mut InterruptController) {
+        let raised = self.advance(now);
+        for (irq, raised) in [IRQ_TIMER0, IRQ_TIMER1, IRQ_TIMER2].into_iter().zip(raised) {
+            if raised {
+                intc.raise(irq);
+            }
+        }
+    }
+
+    // a copy caught up to `now`, for reads while the machine is immutable
+    pub fn peek(&self, now: u64) -> Timers {
+        let mut timers = self.clone();
+        timers.advance(now);
+        timers
+    }
+
+    // clock of the next possible timer interrupt
+    pub fn next_event(&self) -> Option<u64> {
+        [
+            self.dotclock.next_event(),
+            self.hretrace.next_event(),
+            self.sysclock.next_event(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|ticks| self.last_sync + ticks)
+    }
+
+    // reading the mode register clears the reached flags
+    pub fn commit_read_mode(&mut self, addr: u32) {
+        match (addr >> 4) & 0x0F {
+            0 => self.dotclock.commit_read_mode(),
+            1 => self.hretrace.commit_read_mode(),
+            2 => self.sysclock.commit_read_mode(),
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ioport::IO_I_STAT;
+
+    #[test]
+    fn test_sysclock_irq_routed() {
+        let mut timers = Timers::new();
+        let mut intc = InterruptController::new();
+
+        timers.sysclock.write_target(0x0002);
+        // Pulse, Repeat, IRQ on target, Reset on target
+        timers.sysclock.write_mode(0x0058);
+        assert!(timers.next_event().is_some());
+        timers.sync(2, &mut intc);
+        assert_eq!(intc.load(IO_I_STAT).unwrap(), 0x0000);
+        timers.sync(3, &mut intc);
+        assert_eq!(intc.load(IO_I_STAT).unwrap(), 0x0040);
+    }
+
+    #[test]
+    fn test_advance_by_cycles() {
+        let mut timers = Timers::new();
+        let mut intc = InterruptController::new();
+
+        timers.sysclock.write_mode(0x0000);
+        // reads see the caught up state without touching the timers
+        assert_eq!(timers.peek(10).sysclock.read_count(), 9);
+        assert_eq!(timers.sysclock.read_count(), 0);
+        timers.sync(10, &mut intc);
+        // the first tick after a mode write only releases the counter
+        assert_eq!(timers.sysclock.read_count(), 9);
+        assert_eq!(timers.next_event(), None);
+    }
+}
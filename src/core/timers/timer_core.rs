@@ -96,6 +96,80 @@ impl<T: TimerClockSource> Timer<T> {
         }
     }
 
+    // advance by `ticks` system clocks, returns whether an interrupt was raised
+    pub fn advance(&mut self, ticks: u64) -> bool {
+        let mut raised = false;
+        let mut remaining = ticks;
+        while remaining > 0 {
+            let quiet = self.quiet_ticks().min(remaining);
+            if quiet > 0 {
+                self.skip(quiet);
+                remaining -= quiet;
+            } else {
+                self.mutate();
+                raised |= self.interrupt_raised;
+                remaining -= 1;
+            }
+        }
+        raised
+    }
+
+    // ticks until something other than a plain count up can happen
+    fn quiet_ticks(&self) -> u64 {
+        // the pulse IRQ bit is restored on the tick after an interrupt
+        if self.mode & TIMER_IRQ_TOGGLE == 0 && self.mode & TIMER_IRQ_BIT == 0 {
+            return 0;
+        }
+        let countups = if self.freeze {
+            0
+        } else if self.reset_on_target() {
+            if self.count == self.target {
+                0
+            } else {
+                self.target.wrapping_sub(self.count) as u64 - 1
+            }
+        } else {
+            match 0xFFFFu16.wrapping_sub(self.count) {
+                0 => 0xFFFF,
+                dist => dist as u64 - 1,
+            }
+        };
+        self.source
+            .quiet_ticks(&self.clock_params(&TimerVideoTimings::new()), countups)
+    }
+
+    fn skip(&mut self, ticks: u64) {
+        self.interrupt_raised = false;
+        if self.mode & TIMER_IRQ_TOGGLE == 0 {
+            self.mode |= TIMER_IRQ_BIT;
+        }
+        let vtiming = TimerVideoTimings::new();
+        let countups = self.source.skip(&self.clock_params(&vtiming), ticks);
+        self.count = self.count.wrapping_add(countups as u16);
+    }
+
+    // ticks until the timer may raise its next interrupt, None if it can't
+    pub fn next_event(&self) -> Option<u64> {
+        if !self.irq_on_target() && !self.irq_on_ffff() {
+            return None;
+        }
+        if self.interrupt_raised_onshot {
+            return None;
+        }
+        match self.quiet_ticks() {
+            u64::MAX => None,
+            quiet => Some(quiet + 1),
+        }
+    }
+
+    fn clock_params<'a>(&self, vtiming: &'a TimerVideoTimings) -> TimerClockParams<'a> {
+        TimerClockParams {
+            source: self.clock_source(),
+            sync: self.clock_sync(),
+            vtiming,
+        }
+    }
+
     fn raise_interrupt(&mut self) {
         if self.interrupt_raised_onshot {
             return;
@@ -216,10 +290,17 @@ impl TimerClockSourceAction {
 pub trait TimerClockSource {
     fn cycle(&mut self, params: &TimerClockParams) -> TimerClockSourceAction;
     fn reset(&mut self);
+
+    // ticks during which the source counts up at most `countups` times and does nothing else,
+    // u64::MAX when it won't count up at all
+    fn quiet_ticks(&self, params: &TimerClockParams, countups: u64) -> u64;
+    // advances by `ticks` quiet ticks, returns the number of count ups
+    fn skip(&mut self, params: &TimerClockParams, ticks: u64) -> u64;
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::core::timers::timer_dotclock::new_dotclock_timer;
     use crate::core::timers::timer_sysclock::new_sysclock_timer;
 
     use super::*;
@@ -464,4 +545,43 @@ mod tests {
         // 1 -> 0 again
         assert_eq!(timer.interrupt_raised(), true);
     }
+
+    #[test]
+    fn test_advance_matches_ticks() {
+        // free run, reset on target with IRQs, and the divided clock
+        for (mode, target) in [(0x0000, 0), (0x0058, 0x0100), (0x0218, 0x0030), (0x0030, 0)] {
+            let mut ticked = new_sysclock_timer();
+            let mut advanced = new_sysclock_timer();
+            for timer in [&mut ticked, &mut advanced] {
+                timer.write_target(target);
+                timer.write_mode(mode);
+            }
+            let mut raised = false;
+            for step in [1, 5, 100, 1000, 70000] {
+                for _ in 0..step {
+                    ticked.mutate();
+                    raised |= ticked.interrupt_raised();
+                }
+                assert_eq!(advanced.advance(step), raised);
+                raised = false;
+                assert_eq!(advanced.read_count(), ticked.read_count());
+                assert_eq!(advanced.read_mode(), ticked.read_mode());
+            }
+        }
+    }
+
+    #[test]
+    fn test_video_synced_events() {
+        // paused outside of hblank, IRQ on target
+        let mut timer = new_dotclock_timer();
+        timer.write_target(100);
+        timer.write_mode(0x0015);
+        timer.mutate();
+        assert_eq!(timer.next_event(), None);
+
+        // reset on hblank edges, which don't happen between video timing updates
+        timer.write_mode(0x001B);
+        timer.mutate();
+        assert_eq!(timer.next_event(), Some(100));
+    }
 }
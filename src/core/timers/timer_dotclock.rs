@@ -40,6 +40,35 @@ impl TimerClockSource for DotclockSource {
     }
 
     fn reset(&mut self) {}
+
+    // the video timings hold still between updates, the timers are synced before they change
+    fn quiet_ticks(&self, params: &TimerClockParams, countups: u64) -> u64 {
+        if params.vtiming.dotclock_edge || params.vtiming.hblank_edge {
+            0
+        } else if counts_every_tick(params) {
+            countups
+        } else {
+            u64::MAX
+        }
+    }
+
+    fn skip(&mut self, params: &TimerClockParams, ticks: u64) -> u64 {
+        if counts_every_tick(params) {
+            ticks
+        } else {
+            0
+        }
+    }
+}
+
+// system clock source outside of a pause, the dotclock only counts on edges
+fn counts_every_tick(params: &TimerClockParams) -> bool {
+    let paused = match params.sync {
+        Some(0) => params.vtiming.in_hblank,
+        Some(2) => !params.vtiming.in_hblank,
+        _ => false,
+    };
+    !paused && matches!(params.source, 0 | 2)
 }
 
 pub fn new_dotclock_timer() -> Timer<DotclockSource> {
@@ -34,6 +34,35 @@ impl TimerClockSource for HretraceSource {
     }
 
     fn reset(&mut self) {}
+
+    // the video timings hold still between updates, the timers are synced before they change
+    fn quiet_ticks(&self, params: &TimerClockParams, countups: u64) -> u64 {
+        if params.vtiming.hblank_edge || params.vtiming.vblank_edge {
+            0
+        } else if counts_every_tick(params) {
+            countups
+        } else {
+            u64::MAX
+        }
+    }
+
+    fn skip(&mut self, params: &TimerClockParams, ticks: u64) -> u64 {
+        if counts_every_tick(params) {
+            ticks
+        } else {
+            0
+        }
+    }
+}
+
+// system clock source outside of a pause, hblank only counts on edges
+fn counts_every_tick(params: &TimerClockParams) -> bool {
+    let paused = match params.sync {
+        Some(0) => params.vtiming.in_vblank,
+        Some(2) => !params.vtiming.in_vblank,
+        _ => false,
+    };
+    !paused && matches!(params.source, 0 | 2)
 }
 
 pub fn new_hretrace_timer() -> Timer<HretraceSource> {
@@ -30,6 +30,32 @@ impl TimerClockSource for SysclockSource {
     fn reset(&mut self) {
         self.cycles = 0;
     }
+
+    fn quiet_ticks(&self, params: &TimerClockParams, countups: u64) -> u64 {
+        match params.sync {
+            Some(0) | Some(3) => u64::MAX,
+            _ => match params.source {
+                0 | 1 => countups,
+                2 | 3 => (countups + 1) * 7 - 1 - self.cycles as u64,
+                _ => u64::MAX,
+            },
+        }
+    }
+
+    fn skip(&mut self, params: &TimerClockParams, ticks: u64) -> u64 {
+        match params.sync {
+            Some(0) | Some(3) => 0,
+            _ => match params.source {
+                0 | 1 => ticks,
+                2 | 3 => {
+                    let total = self.cycles as u64 + ticks;
+                    self.cycles = (total % 7) as u32;
+                    total / 7
+                }
+                _ => 0,
+            },
+        }
+    }
 }
 
 pub fn new_sysclock_timer() -> Timer<SysclockSource> {
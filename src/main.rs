@@ -1,7 +1,11 @@
 use std::fs;
 use std::fs::File;
 use std::io::stdin;
+use std::io::stdout;
 use std::io::Read;
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
 use clap::Parser;
 use pprof::protos::Message;
@@ -16,6 +20,12 @@ struct Args {
     bios: String,
     #[arg(short, long)]
     state: Option<String>,
+    // parallel port cartridge ROM
+    #[arg(short, long)]
+    cart: Option<String>,
+    // bridge the kernel TTY on the DUART to stdin and stdout
+    #[arg(short, long)]
+    tty: bool,
 }
 
 fn load_bios(path: &str) -> Vec<u8> {
@@ -43,6 +53,20 @@ fn load_state(path: &str) -> Option<MachineState> {
     }
 }
 
+// stdin is read off the emulation thread, the machine polls for what arrived
+fn spawn_tty_input() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(n @ 1..) = stdin().read(&mut buf) {
+            if sender.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
 fn main() {
     let guard = pprof::ProfilerGuardBuilder::default()
         .frequency(1000)
@@ -56,16 +80,34 @@ fn main() {
 
     let mut machine = Machine::new(bios);
 
+    if let Some(cart_path) = args.cart {
+        machine
+            .insert_cartridge(load_bios(&cart_path))
+            .expect("failed to insert cartridge");
+    }
+
     machine.reset();
 
     if let Some(state_path) = args.state {
         if let Some(state) = load_state(&state_path) {
-            machine.load_state(state);
+            machine.load_state(state).expect("failed to restore state");
         }
     }
 
+    let tty_input = args.tty.then(spawn_tty_input);
+
     loop {
         machine.cycle().expect("Failed to cycle");
+        // taken even without --tty so it doesn't pile up in the DUART and save states
+        let output = machine.take_tty_output();
+        if args.tty && !output.is_empty() {
+            stdout().write_all(&output).expect("failed to write tty");
+        }
+        if let Some(input) = &tty_input {
+            while let Ok(bytes) = input.try_recv() {
+                machine.push_tty_input(&bytes);
+            }
+        }
         if machine.cpu.current_pc() == 0x80030000 {
             let state = machine.save_state();
             save_state("state.bin", state);